@@ -0,0 +1,545 @@
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use log::{debug, info, warn};
+
+use crate::cache::CACHE_DIR_DEFAULT;
+use crate::config::{read_config, CONFIG_DEFAULT};
+use crate::error::Error;
+use crate::hash::HashAlgo;
+use crate::param::{Parameter, PKG_DEFAULT, REV_DEFAULT};
+use crate::pattern::{compile, exact_name, is_pattern};
+use crate::template::{self, MODULE_TEMPLATE_DEFAULT, TOP_TEMPLATE_DEFAULT};
+
+enum PNext {
+    #[allow(non_camel_case_types)] P_TOP,
+    #[allow(non_camel_case_types)] P_EXPECT_TOPS,
+    #[allow(non_camel_case_types)] P_BB,
+    #[allow(non_camel_case_types)] P_REV,
+    #[allow(non_camel_case_types)] P_PKG,
+    #[allow(non_camel_case_types)] P_OUT,
+    #[allow(non_camel_case_types)] P_MANIFEST,
+    #[allow(non_camel_case_types)] P_AGAINST,
+    #[allow(non_camel_case_types)] P_STUB,
+    #[allow(non_camel_case_types)] P_STUB_HEADER,
+    #[allow(non_camel_case_types)] P_HASH,
+    #[allow(non_camel_case_types)] P_HASH_WIDTH,
+    #[allow(non_camel_case_types)] P_TOP_TPL,
+    #[allow(non_camel_case_types)] P_MODULE_TPL,
+    #[allow(non_camel_case_types)] P_JOBS,
+    #[allow(non_camel_case_types)] P_CACHE,
+    #[allow(non_camel_case_types)] P_LIB,
+    #[allow(non_camel_case_types)] P_NONE
+}
+
+use PNext::*;
+
+// expand $VAR and ${VAR} from environment
+pub(crate) fn expand_env(s: &str) -> String {
+    let mut res = String::new();
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('$') {
+        res.push_str(&rest[..idx]);
+        rest = &rest[idx+1..];
+
+        let (name, len) = if let Some(r) = rest.strip_prefix('{') {
+            match r.find('}') {
+                Some(end) => (&r[..end], end + 2),
+                None      => ("", 0),
+            }
+        }
+        else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            (&rest[..end], end)
+        };
+
+        if name.is_empty() {
+            res.push('$');
+            continue;
+        }
+
+        match env::var(name) {
+            Ok(v)  => res.push_str(&v),
+            Err(_) => warn!("environment variable {} not set", name),
+        }
+
+        rest = &rest[len..];
+    }
+
+    res.push_str(rest);
+    res
+}
+
+pub(crate) fn rebase(base: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() { path.to_string() }
+    else { base.join(path).to_string_lossy().to_string() }
+}
+
+// `//` start comment only at line start or after whitespace, not inside token like +define+URL=http://x
+fn strip_comment(line: &str) -> &str {
+    let mut after_space = true;
+
+    for (i, c) in line.char_indices() {
+        if after_space && line[i..].starts_with("//") { return &line[..i]; }
+        after_space = c.is_whitespace();
+    }
+
+    line
+}
+
+// read a filelist into argument list, with -F all relative path, nested -f/-F included, be based on filelist directory
+fn read_filelist(path: &str, relative: bool) -> Result<Vec<String>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| Error::Io(path.to_string(), e))?;
+
+    let mut tokens: Vec<String> = Vec::new();
+
+    for line in content.lines() {
+        for t in strip_comment(line).split_whitespace() {
+            if t.starts_with('#') { break; }
+            tokens.push(expand_env(t));
+        }
+    }
+
+    if !relative { return Ok(tokens); }
+
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut res: Vec<String> = Vec::new();
+    let mut prev: Option<String> = None;
+
+    for t in tokens.into_iter() {
+        let t1 = match prev.as_deref() {
+            Some("-t") | Some("-r") | Some("-p") | Some("-b") |
+            Some("--hash") | Some("--hash-width") | Some("--expect-tops") | Some("-j") |
+            Some("--top-template") | Some("--module-template")            => t.clone(),
+            Some("-f") | Some("-F") | Some("-o") | Some("-y") | Some("-v") |
+            Some("--manifest") | Some("--against") | Some("--config") |
+            Some("--stub") | Some("--stub-header") | Some("--cache-dir")  => rebase(base, &t),
+            _ => {
+                if let Some(dir) = t.strip_prefix("+incdir+") { format!("+incdir+{}", rebase(base, dir)) }
+                else if t.starts_with('-') || t.starts_with('+') { t.clone() }
+                else { rebase(base, &t) }
+            }
+        };
+
+        prev = Some(t);
+        res.push(t1);
+    }
+
+    Ok(res)
+}
+
+// recursively replace -f/-F <filelist> with its content
+fn expand_args(args: Vec<String>, stack: &mut Vec<PathBuf>) -> Result<Vec<String>, Error> {
+    let mut res: Vec<String> = Vec::new();
+    let mut iter = args.into_iter();
+
+    while let Some(arg) = iter.next() {
+        if arg != "-f" && arg != "-F" {
+            res.push(arg);
+            continue;
+        }
+
+        let path = iter.next().ok_or_else(|| Error::Arg(format!("missing filelist after {}", arg)))?;
+
+        let abs = fs::canonicalize(&path).unwrap_or_else(|_| PathBuf::from(&path));
+        if stack.contains(&abs) {
+            return Err(Error::Arg(format!("filelist {} include itself recursively", path)));
+        }
+
+        debug!("read filelist {}", path);
+
+        let tokens = read_filelist(&path, arg == "-F")?;
+
+        stack.push(abs);
+        res.extend(expand_args(tokens, stack)?);
+        stack.pop();
+    }
+
+    Ok(res)
+}
+
+// only warn when an option is given twice in the same place, config is meant to be overrided by command line
+fn overrided(set_by: &mut BTreeMap<&'static str, bool>, opt: &'static str, from_cfg: bool) -> bool {
+    set_by.insert(opt, from_cfg) == Some(from_cfg)
+}
+
+// list from command line replace the one from config instead of extending it
+fn replaced(list_by: &mut BTreeMap<&'static str, bool>, list: &'static str, from_cfg: bool) -> bool {
+    list_by.insert(list, from_cfg) == Some(true) && !from_cfg
+}
+
+pub fn parse_args(args: Vec<String>) -> Result<Parameter, Error> {
+    let mut file_list: Vec<String> = Vec::new();
+    let mut defines: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut inc_list: Vec<String> = Vec::new();
+    let mut top_set: BTreeSet<String> = BTreeSet::new();
+    let mut bb_set: BTreeSet<String> = BTreeSet::new();
+    let mut top_patterns: Vec<String> = Vec::new();
+    let mut bb_patterns: Vec<String> = Vec::new();
+    let mut auto_top = false;
+    let mut expect_tops: Option<usize> = None;
+
+    let mut rev: usize = REV_DEFAULT;
+    let mut pkg: String = PKG_DEFAULT.into();
+
+    let mut out_dir: Option<String> = None;
+    let mut manifest: Option<String> = None;
+    let mut stub: Option<String> = None;
+    let mut stub_header: Option<String> = None;
+    let mut rename_file = false;
+    let mut prune = false;
+    let mut define_report = false;
+    let mut force = false;
+    let mut strip_comments = false;
+    let mut keep_lines = false;
+    let mut keep_header = false;
+    let mut minify = false;
+    let mut preprocessed = false;
+    let mut preserve_directives = false;
+    let mut verify = false;
+    let mut dump_config = false;
+    let mut against: Option<String> = None;
+    let mut hash = HashAlgo::Crc32;
+    let mut hash_width: Option<usize> = None;
+    let mut legacy_hash = false;
+    let mut content_addressed = false;
+    let mut jobs: Option<usize> = None;
+    let mut cache_dir: String = CACHE_DIR_DEFAULT.into();
+    let mut no_cache = false;
+    let mut top_template: String = TOP_TEMPLATE_DEFAULT.into();
+    let mut module_template: String = MODULE_TEMPLATE_DEFAULT.into();
+
+    let mut pnext: PNext = P_NONE;
+
+    let mut args = args;
+    if args.first().map(|x| x == "verify").unwrap_or(false) {
+        verify = true;
+        args.remove(0);
+    }
+
+    // --config given explicitly, or shim-release.toml in working directory
+    let mut config: Option<String> = None;
+    let mut cli: Vec<String> = Vec::new();
+    let mut iter = expand_args(args, &mut Vec::new())?.into_iter();
+
+    while let Some(arg) = iter.next() {
+        if arg != "--config" {
+            cli.push(arg);
+            continue;
+        }
+
+        if let Some(c) = config { warn!("old config {} be overrided", c) }
+        config = Some(iter.next().ok_or_else(|| Error::Arg("missing config after --config".to_string()))?);
+    }
+
+    if config.is_none() && Path::new(CONFIG_DEFAULT).is_file() { config = Some(CONFIG_DEFAULT.to_string()); }
+
+    let cfg_args = match &config {
+        Some(c) => {
+            info!("read config {}", c);
+            expand_args(read_config(c)?, &mut Vec::new())?
+        }
+        None => Vec::new(),
+    };
+
+    // config go first, so command line override its single values, `--no-<flag>` & `--cache` turn off its flags,
+    // -t, -b, files & +incdir+ from command line replace its lists, +define+ override its defines by name
+    let mut set_by: BTreeMap<&'static str, bool> = BTreeMap::new();
+    let mut list_by: BTreeMap<&'static str, bool> = BTreeMap::new();
+    let all = cfg_args.into_iter().map(|x| (true, x)).chain(cli.into_iter().map(|x| (false, x)));
+
+    for (from_cfg, arg) in all {
+        match pnext {
+            P_NONE => {
+                if (arg.len() >= 8) && (&arg[0..8] == "+define+") {
+                    let kv = &arg[8..];
+                    let (k, v) = match kv.find('=') {
+                        None => (kv.to_string(), None),
+                        Some(idx) => (kv[0..idx].to_string(), Some(kv[idx+1..].to_string()))
+                    };
+
+                    defines.insert(k, v);
+                }
+                else if (arg.len() > 8) && (&arg[0..8] == "+incdir+") {
+                    if replaced(&mut list_by, "+incdir+", from_cfg) { inc_list.clear(); }
+                    inc_list.push(arg[8..].to_string());
+                }
+                else if arg.starts_with("+libext+") { debug!("ignore {}", arg); }
+                else if arg == "-y" || arg == "-v" {
+                    warn!("library option {} is not supported, ignored", arg);
+                    pnext = P_LIB;
+                }
+                else if arg == "-t" { pnext = P_TOP; }
+                else if arg == "--auto-top" { auto_top = true; }
+                else if arg == "--no-auto-top" { auto_top = false; }
+                else if arg == "--expect-tops" { pnext = P_EXPECT_TOPS; }
+                else if arg == "-r" { pnext = P_REV; }
+                else if arg == "-p" { pnext = P_PKG; }
+                else if arg == "-b" { pnext = P_BB; }
+                else if arg == "-o" { pnext = P_OUT; }
+                else if arg == "--manifest" { pnext = P_MANIFEST; }
+                else if arg == "--against" { pnext = P_AGAINST; }
+                else if arg == "--stub" { pnext = P_STUB; }
+                else if arg == "--stub-header" { pnext = P_STUB_HEADER; }
+                else if arg == "--hash" { pnext = P_HASH; }
+                else if arg == "--hash-width" { pnext = P_HASH_WIDTH; }
+                else if arg == "--legacy-hash" { legacy_hash = true; }
+                else if arg == "--no-legacy-hash" { legacy_hash = false; }
+                else if arg == "--content-addressed" { content_addressed = true; }
+                else if arg == "--no-content-addressed" { content_addressed = false; }
+                else if arg == "-j" { pnext = P_JOBS; }
+                else if arg == "--cache-dir" { pnext = P_CACHE; }
+                else if arg == "--no-cache" { no_cache = true; }
+                else if arg == "--cache" { no_cache = false; }
+                else if arg == "--top-template" { pnext = P_TOP_TPL; }
+                else if arg == "--module-template" { pnext = P_MODULE_TPL; }
+                else if arg == "--rename-file" { rename_file = true; }
+                else if arg == "--no-rename-file" { rename_file = false; }
+                else if arg == "--prune" { prune = true; }
+                else if arg == "--no-prune" { prune = false; }
+                else if arg == "--define-report" { define_report = true; }
+                else if arg == "--no-define-report" { define_report = false; }
+                else if arg == "--force" { force = true; }
+                else if arg == "--no-force" { force = false; }
+                else if arg == "--strip-comments" { strip_comments = true; }
+                else if arg == "--no-strip-comments" { strip_comments = false; }
+                else if arg == "--keep-lines" { keep_lines = true; }
+                else if arg == "--no-keep-lines" { keep_lines = false; }
+                else if arg == "--keep-license-header" { keep_header = true; }
+                else if arg == "--no-keep-license-header" { keep_header = false; }
+                else if arg == "--minify" { minify = true; }
+                else if arg == "--no-minify" { minify = false; }
+                else if arg == "--preprocessed" { preprocessed = true; }
+                else if arg == "--no-preprocessed" { preprocessed = false; }
+                else if arg == "--preserve-directives" { preserve_directives = true; }
+                else if arg == "--no-preserve-directives" { preserve_directives = false; }
+                else if arg == "--dump-config" { dump_config = true; }
+                else if arg.starts_with('-') {
+                    return Err(Error::Arg(format!("unknown option {}", arg)));
+                }
+                else {
+                    if replaced(&mut list_by, "files", from_cfg) { file_list.clear(); }
+                    file_list.push(arg)
+                }
+            },
+            P_TOP => {
+                if replaced(&mut list_by, "-t", from_cfg) { top_set.clear(); top_patterns.clear(); }
+                if !is_pattern(&arg) { top_set.insert(exact_name(arg)); }
+                else {
+                    compile(&arg).map_err(Error::Arg)?;
+                    top_patterns.push(arg);
+                }
+                pnext = P_NONE;
+            },
+            P_EXPECT_TOPS => {
+                expect_tops = Some(arg.parse().map_err(|_| Error::Arg(format!("invalid top count '{}'", arg)))?);
+                pnext = P_NONE;
+            },
+            P_BB => {
+                if replaced(&mut list_by, "-b", from_cfg) { bb_set.clear(); bb_patterns.clear(); }
+                if !is_pattern(&arg) { bb_set.insert(exact_name(arg)); }
+                else {
+                    compile(&arg).map_err(Error::Arg)?;
+                    bb_patterns.push(arg);
+                }
+                pnext = P_NONE;
+            },
+            P_REV => {
+                if overrided(&mut set_by, "-r", from_cfg) { warn!("old revision {} be overrided", rev) }
+                rev = arg.parse().map_err(|_| Error::Arg(format!("invalid revision '{}'", arg)))?;
+                pnext = P_NONE;
+            },
+            P_PKG => {
+                if overrided(&mut set_by, "-p", from_cfg) { warn!("old package {} be overrided", pkg) }
+                pkg = arg;
+                pnext = P_NONE;
+            },
+            P_LIB => {
+                debug!("ignore library {}", arg);
+                pnext = P_NONE;
+            },
+            P_OUT => {
+                if let (Some(d), true) = (&out_dir, overrided(&mut set_by, "-o", from_cfg)) {
+                    warn!("old output directory {} be overrided", d)
+                }
+                out_dir = Some(arg);
+                pnext = P_NONE;
+            },
+            P_MANIFEST => {
+                if let (Some(m), true) = (&manifest, overrided(&mut set_by, "--manifest", from_cfg)) {
+                    warn!("old manifest {} be overrided", m)
+                }
+                manifest = Some(arg);
+                pnext = P_NONE;
+            },
+            P_AGAINST => {
+                if let (Some(a), true) = (&against, overrided(&mut set_by, "--against", from_cfg)) {
+                    warn!("old release {} be overrided", a)
+                }
+                against = Some(arg);
+                pnext = P_NONE;
+            },
+            P_STUB => {
+                stub = Some(arg);
+                pnext = P_NONE;
+            },
+            P_STUB_HEADER => {
+                stub_header = Some(arg);
+                pnext = P_NONE;
+            },
+            P_HASH => {
+                hash = HashAlgo::from_name(&arg)
+                    .ok_or_else(|| Error::Arg(format!("unknown hash '{}', use crc32, crc64 or sha256", arg)))?;
+                pnext = P_NONE;
+            },
+            P_HASH_WIDTH => {
+                hash_width = Some(arg.parse().map_err(|_| Error::Arg(format!("invalid hash width '{}'", arg)))?);
+                pnext = P_NONE;
+            },
+            P_JOBS => {
+                jobs = match arg.parse() {
+                    Ok(0) | Err(_) => return Err(Error::Arg(format!("invalid job count '{}'", arg))),
+                    Ok(n) => Some(n),
+                };
+                pnext = P_NONE;
+            },
+            P_CACHE => {
+                cache_dir = arg;
+                pnext = P_NONE;
+            },
+            P_TOP_TPL => {
+                top_template = arg;
+                pnext = P_NONE;
+            },
+            P_MODULE_TPL => {
+                module_template = arg;
+                pnext = P_NONE;
+            },
+        }
+    }
+
+    match pnext {
+        P_NONE => (),
+        _      => return Err(Error::Arg("missing value for last option".to_string())),
+    }
+
+    if expect_tops.is_some() && !auto_top { warn!("--expect-tops has no effect without --auto-top") }
+
+    if prune && top_set.is_empty() && top_patterns.is_empty() && !auto_top {
+        return Err(Error::Arg("--prune need top given by -t or --auto-top".to_string()));
+    }
+
+    if preprocessed && preserve_directives {
+        return Err(Error::Arg("--preprocessed and --preserve-directives can not be used together".to_string()));
+    }
+
+    if preserve_directives && (strip_comments || minify) {
+        return Err(Error::Arg("--strip-comments and --minify need preprocessed text, not --preserve-directives".to_string()));
+    }
+
+    if stub_header.is_some() && stub.is_none() { warn!("--stub-header has no effect without --stub") }
+
+    if no_cache && cache_dir != CACHE_DIR_DEFAULT { warn!("--cache-dir has no effect with --no-cache") }
+    let cache_dir = if no_cache { None } else { Some(cache_dir) };
+
+    if content_addressed {
+        if !top_template.contains("{rev}") && !top_template.contains("{pkg}") {
+            warn!("top template '{}' has no {{rev}} or {{pkg}}, tops of different releases may share name",
+                  top_template);
+        }
+        if module_template.contains("{rev}") || module_template.contains("{pkg}") {
+            warn!("module template '{}' has {{rev}} or {{pkg}}, identical modules of different releases still differ",
+                  module_template);
+        }
+    }
+
+    if minify && keep_lines { warn!("--keep-lines has no effect with --minify") }
+
+    if let Some(w) = hash_width {
+        if w == 0 || w > hash.max_width() {
+            return Err(Error::Arg(format!("hash width of {} should between 1 and {}", hash.name(), hash.max_width())));
+        }
+    }
+
+    let p = Parameter { file_list, defines, inc_list, bb_set, top_set, top_patterns, bb_patterns, auto_top,
+                        expect_tops, rev, pkg, out_dir, manifest, stub, stub_header, rename_file, define_report,
+                        prune, force, strip_comments, keep_lines, keep_header, minify, preprocessed,
+                        preserve_directives, verify, dump_config, against, hash, hash_width, legacy_hash,
+                        content_addressed, jobs, cache_dir, top_template, module_template };
+
+    template::check(&p).map_err(Error::Arg)?;
+
+    Ok(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use super::{parse_args, strip_comment};
+
+    #[test]
+    fn comment_only_after_whitespace() {
+        assert_eq!(strip_comment("+define+URL=http://x // note"), "+define+URL=http://x ");
+        assert_eq!(strip_comment("// whole line"), "");
+        assert_eq!(strip_comment("a.sv\t// tab"), "a.sv\t");
+        assert_eq!(strip_comment("dir//a.sv"), "dir//a.sv");
+    }
+
+    #[test]
+    fn command_line_override_config() {
+        let dir = env::temp_dir().join(format!("shim-release-args-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cfg = dir.join("c.toml");
+        fs::write(&cfg, "top = [\"top\"]\nfiles = [\"a.sv\"]\nincdirs = [\"inc\"]\nprune = true\nno_cache = true\n\
+                         [defines]\nA = 1\nB = 2\n").unwrap();
+
+        let args = |v: &[&str]| -> Vec<String> {
+            let mut res = vec!["--config".to_string(), cfg.to_string_lossy().to_string()];
+            res.extend(v.iter().map(|x| x.to_string()));
+            res
+        };
+
+        let p = parse_args(args(&[])).unwrap();
+        assert!(p.prune && p.cache_dir.is_none() && p.top_set.contains("top") && p.file_list.len() == 1);
+
+        let p = parse_args(args(&["-t", "top2", "--no-prune", "--cache", "+incdir+x", "+define+B=3", "b.sv"])).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(p.top_set.iter().collect::<Vec<_>>(), ["top2"]);
+        assert_eq!(p.file_list, ["b.sv"]);
+        assert_eq!(p.inc_list, ["x"]);
+        assert_eq!(p.defines["A"].as_deref(), Some("1"));
+        assert_eq!(p.defines["B"].as_deref(), Some("3"));
+        assert!(!p.prune && p.cache_dir.is_some());
+    }
+
+    // -f & -F operand inside -F list based on its directory, content of -f list on working directory
+    #[test]
+    fn nested_filelist() {
+        let dir = env::temp_dir().join(format!("shim-release-filelist-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub/deep")).unwrap();
+        let d = dir.to_string_lossy().to_string();
+
+        env::set_var("SHIM_RELEASE_TEST_DIR", "gen");
+        fs::write(dir.join("sub/list.F"), "# comment line
+-f other.f
+-F deep/d.F // trailing
+                                           $SHIM_RELEASE_TEST_DIR/x.sv ${SHIM_RELEASE_TEST_DIR}_y.sv
+                                           +incdir+inc +define+URL=http://x # note
+").unwrap();
+        fs::write(dir.join("sub/other.f"), "a.sv
+").unwrap();
+        fs::write(dir.join("sub/deep/d.F"), "b.sv
+").unwrap();
+
+        let p = parse_args(vec!["-F".to_string(), format!("{}/sub/list.F", d)]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let expect: Vec<String> = ["a.sv".to_string(), format!("{}/sub/deep/b.sv", d),
+                                   format!("{}/sub/gen/x.sv", d), format!("{}/sub/gen_y.sv", d)].to_vec();
+        assert_eq!(p.file_list, expect);
+        assert_eq!(p.inc_list, [format!("{}/sub/inc", d)]);
+        assert_eq!(p.defines["URL"].as_deref(), Some("http://x"));
+    }
+}
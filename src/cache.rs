@@ -0,0 +1,422 @@
+use std::{fs, process};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::collections::{BTreeMap, BTreeSet};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+
+use crate::hash::{Checksum, HashAlgo, Hasher};
+use crate::param::Parameter;
+use crate::rewrite::{new_name, Analysis, Fact, FileFacts, Output};
+
+pub const CACHE_DIR_DEFAULT: &str = ".shim-release-cache";
+
+// bump when layout of cache entry, 1st pass result or output text change
+const CACHE_VERSION: &str = "6";
+
+// entry not used for this long is removed when cache opened, used one is touched
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+
+const KINDS: &[&str] = &["module", "interface", "program", "udp", "package", "class"];
+
+// 1st pass result & output text of unchanged files, entry named by sha256 of everything it depend on
+pub struct Cache {
+    dir: Option<PathBuf>,
+    // input path -> key of content, defines, include path, pkg, rev & hash setting
+    keys: BTreeMap<String, String>,
+    facts: BTreeMap<String, FileFacts>,
+    // content hash of included files, none when unreadable
+    dep_hash: BTreeMap<String, Option<String>>,
+}
+
+fn field(h: &mut Hasher, s: &str) {
+    h.update(s.as_bytes());
+    h.update(b"\0");
+}
+
+fn content_hash(path: &str) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    let mut h = Hasher::new(HashAlgo::Sha256);
+    h.update(&bytes);
+    Some(h.finalize().to_string())
+}
+
+fn file_key(p: &Parameter, path: &str) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    let mut h = Hasher::new(HashAlgo::Sha256);
+
+    let token_hash = if p.legacy_hash { "legacy" } else { "canonical" };
+    let salt = if p.content_addressed { "content" } else { "release" };
+
+    for s in [CACHE_VERSION, env!("CARGO_PKG_VERSION"), path, &p.pkg, &p.rev.to_string(), p.hash.name(),
+              token_hash, salt] {
+        field(&mut h, s);
+    }
+
+    for (k, v) in p.defines.iter() {
+        match v {
+            None     => field(&mut h, k),
+            Some(v1) => field(&mut h, &format!("{}={}", k, v1)),
+        }
+    }
+
+    for i in p.inc_list.iter() { field(&mut h, &format!("+incdir+{}", i)); }
+
+    h.update(&bytes);
+    Some(h.finalize().to_string())
+}
+
+fn write_facts(f: &FileFacts, deps: &[(String, String)]) -> String {
+    let facts: Vec<Value> = f.facts.iter().map(|x| match x {
+        Fact::Decl { name, kind, file, line } => json!(["decl", name, kind, file, line]),
+        Fact::Inst { name, owner }            => json!(["inst", name, owner]),
+        Fact::Digest { name, digest }         => json!(["digest", name, digest.to_string()]),
+    }).collect();
+
+    let rename: Vec<Value> = f.rename.iter()
+        .map(|((o, l, n), name, decl)| json!([o, l, n, name, decl]))
+        .collect();
+
+    let soft_ref: Vec<Value> = f.soft_ref.iter()
+        .map(|((o, l, n), name, owner, only)| json!([o, l, n, name, owner, only]))
+        .collect();
+
+    let port_use: Vec<Value> = f.port_use.iter()
+        .map(|(m, u)| json!([m, u.kind, u.ports, u.ordered_ports, u.params, u.ordered_params]))
+        .collect();
+
+    json!({ "deps": deps, "facts": facts, "rename": rename, "soft_ref": soft_ref, "port_use": port_use }).to_string()
+}
+
+fn usize_of(j: &Value) -> Option<usize> {
+    j.as_u64().map(|x| x as usize)
+}
+
+// string or null
+fn opt_str(j: &Value) -> Option<Option<String>> {
+    match j { Value::String(s) => Some(Some(s.clone())), Value::Null => Some(None), _ => None }
+}
+
+// facts & recorded content hash of dependencies
+fn read_facts(algo: HashAlgo, s: &str) -> Option<(FileFacts, Vec<(String, String)>)> {
+    let json: Value = serde_json::from_str(s).ok()?;
+    let mut res = FileFacts::default();
+
+    let strings = |j: &Value| -> Option<Vec<String>> {
+        j.as_array()?.iter().map(|x| x.as_str().map(|s| s.to_string())).collect()
+    };
+    let kind = |j: &Value| -> Option<&'static str> { KINDS.iter().find(|k| Some(**k) == j.as_str()).copied() };
+
+    let mut deps: Vec<(String, String)> = Vec::new();
+    for x in json.get("deps")?.as_array()?.iter() {
+        deps.push((x.get(0)?.as_str()?.to_string(), x.get(1)?.as_str()?.to_string()));
+        res.deps.insert(x.get(0)?.as_str()?.to_string());
+    }
+
+    for v in json.get("facts")?.as_array()?.iter() {
+        let name = v.get(1)?.as_str()?.to_string();
+
+        res.facts.push(match v.get(0)?.as_str()? {
+            "decl"   => Fact::Decl { name, kind: kind(v.get(2)?)?, file: v.get(3)?.as_str()?.to_string(), line: usize_of(v.get(4)?)? },
+            "inst"   => Fact::Inst { name, owner: opt_str(v.get(2)?)? },
+            "digest" => Fact::Digest { name, digest: Checksum::from_hex(algo, v.get(2)?.as_str()?)? },
+            _ => return None,
+        });
+    }
+
+    for v in json.get("rename")?.as_array()?.iter() {
+        let loc = (usize_of(v.get(0)?)?, usize_of(v.get(1)?)?, usize_of(v.get(2)?)? as u32);
+        res.rename.push((loc, v.get(3)?.as_str()?.to_string(), v.get(4)?.as_bool()?));
+    }
+
+    for v in json.get("soft_ref")?.as_array()?.iter() {
+        let loc = (usize_of(v.get(0)?)?, usize_of(v.get(1)?)?, usize_of(v.get(2)?)? as u32);
+        res.soft_ref.push((loc, v.get(3)?.as_str()?.to_string(), opt_str(v.get(4)?)?, v.get(5)?.as_bool()?));
+    }
+
+    for v in json.get("port_use")?.as_array()?.iter() {
+        let u = res.port_use.entry(v.get(0)?.as_str()?.to_string()).or_default();
+
+        u.kind = kind(v.get(1)?)?;
+        u.ports = strings(v.get(2)?)?;
+        u.ordered_ports = usize_of(v.get(3)?)?;
+        u.params = strings(v.get(4)?)?;
+        u.ordered_params = usize_of(v.get(5)?)?;
+    }
+
+    Some((res, deps))
+}
+
+// mark entry as used, so it is not evicted
+fn touch(path: &Path) {
+    let _ = fs::File::options().append(true).open(path).and_then(|f| f.set_modified(SystemTime::now()));
+}
+
+// remove entries, and temporary files left by killed run, unused for MAX_AGE
+fn evict(dir: &Path) {
+    let now = SystemTime::now();
+    let mut count = 0;
+
+    for e in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = e.path();
+        let ours = matches!(path.extension().and_then(|x| x.to_str()), Some("facts") | Some("out") | Some("tmp"));
+        let old = e.metadata().and_then(|m| m.modified()).ok()
+            .and_then(|t| now.duration_since(t).ok())
+            .map(|d| d > MAX_AGE)
+            .unwrap_or(false);
+
+        if ours && old && fs::remove_file(&path).is_ok() { count += 1; }
+    }
+
+    if count > 0 { info!("cache {}, {} stale entries removed", dir.display(), count); }
+}
+
+impl Cache {
+    pub fn open(p: &Parameter) -> Cache {
+        let mut res = Cache { dir: None, keys: BTreeMap::new(), facts: BTreeMap::new(), dep_hash: BTreeMap::new() };
+
+        let dir = match &p.cache_dir {
+            Some(d) => PathBuf::from(d),
+            None => return res,
+        };
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("can not create cache {}: {}, cache disabled", dir.display(), e);
+            return res;
+        }
+
+        evict(&dir);
+
+        for f in p.file_list.iter() {
+            // unreadable file reported by parser
+            let key = match file_key(p, f) {
+                Some(k) => k,
+                None => continue,
+            };
+
+            let entry_path = dir.join(format!("{}.facts", key));
+            let entry = fs::read_to_string(&entry_path).ok()
+                .and_then(|s| read_facts(p.hash, &s));
+
+            if let Some((facts, deps)) = entry {
+                let fresh = deps.iter().all(|(d, h)| {
+                    res.dep_hash.entry(d.clone()).or_insert_with(|| content_hash(d)).as_ref() == Some(h)
+                });
+
+                if fresh {
+                    touch(&entry_path);
+                    res.facts.insert(f.clone(), facts);
+                }
+                else { debug!("  {} include changed file", f); }
+            }
+
+            res.keys.insert(f.clone(), key);
+        }
+
+        info!("cache {}, {} of {} files unchanged", dir.display(), res.facts.len(), p.file_list.len());
+
+        res.dir = Some(dir);
+        res
+    }
+
+    // input files with 1st pass result
+    pub(crate) fn paths(&self) -> impl Iterator<Item = &String> {
+        self.facts.keys()
+    }
+
+    pub(crate) fn facts(&self, path: &str) -> Option<&FileFacts> {
+        self.facts.get(path)
+    }
+
+    fn write(&self, name: &str, s: &str) {
+        let dir = match &self.dir {
+            Some(d) => d,
+            None => return,
+        };
+
+        // rename into place, for concurrent runs sharing the cache
+        let tmp = dir.join(format!("{}.{}.tmp", name, process::id()));
+        let dest = dir.join(name);
+
+        if let Err(e) = fs::write(&tmp, s).and_then(|_| fs::rename(&tmp, &dest)) {
+            warn!("  can not write cache {}: {}", dest.display(), e);
+        }
+    }
+
+    pub(crate) fn store_facts(&mut self, path: &str, facts: FileFacts) -> &FileFacts {
+        if let (Some(_), Some(key)) = (&self.dir, self.keys.get(path)) {
+            let mut deps: Vec<(String, String)> = Vec::new();
+
+            for d in facts.deps.iter() {
+                if let Some(h) = self.dep_hash.entry(d.clone()).or_insert_with(|| content_hash(d)) {
+                    deps.push((d.clone(), h.clone()));
+                }
+            }
+
+            self.write(&format!("{}.facts", key), &write_facts(&facts, &deps));
+        }
+
+        self.facts.insert(path.to_string(), facts);
+        &self.facts[path]
+    }
+
+    // output depend on content, included files, output mode and new names of identifiers in file
+    pub(crate) fn output_key(&self, p: &Parameter, a: &Analysis, path: &str, pruned: &BTreeSet<String>) -> Option<String> {
+        self.dir.as_ref()?;
+
+        let facts = self.facts.get(path)?;
+        let mut h = Hasher::new(HashAlgo::Sha256);
+
+        field(&mut h, self.keys.get(path)?);
+
+        for d in facts.deps.iter() {
+            field(&mut h, self.dep_hash.get(d)?.as_ref()?);
+        }
+
+        let mode = [p.strip_comments, p.keep_lines, p.keep_header, p.minify, p.preprocessed, p.preserve_directives];
+        field(&mut h, &format!("{:?}", mode));
+
+        for ((f, offset, _, _), (name, _)) in a.rename_map.range((path.to_string(), 0, 0, 0)..) {
+            if f != path { break; }
+            field(&mut h, &format!("{} {} {}", offset, name, new_name(p, &a.module_map, name).unwrap_or_default()));
+        }
+
+        for (_, name, _) in facts.rename.iter().filter(|x| x.2 && pruned.contains(&x.1)) {
+            field(&mut h, &format!("pruned {}", name));
+        }
+
+        Some(h.finalize().to_string())
+    }
+
+    // output, none for file pruned entirely, with warnings when it was written
+    pub(crate) fn output(&self, key: &str) -> Option<(Option<Output>, Vec<String>)> {
+        let path = self.dir.as_ref()?.join(format!("{}.out", key));
+        let s = fs::read_to_string(&path).ok()?;
+        let json: Value = serde_json::from_str(&s).ok()?;
+
+        let warns: Option<Vec<String>> = json.get("warnings")?.as_array()?.iter()
+            .map(|x| x.as_str().map(|w| w.to_string()))
+            .collect();
+
+        let o = match json.get("output")? {
+            Value::Null => None,
+            x => Some(Output {
+                text: x.get("text")?.as_str()?.to_string(),
+                first_module: opt_str(x.get("first_module")?)?,
+            }),
+        };
+
+        touch(&path);
+        Some((o, warns?))
+    }
+
+    pub(crate) fn store_output(&self, key: &str, o: Option<&Output>, warns: &[String]) {
+        let o = o.map(|o| json!({ "first_module": o.first_module, "text": o.text }));
+
+        self.write(&format!("{}.out", key), &json!({ "output": o, "warnings": warns }).to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use super::{evict, MAX_AGE};
+    use crate::{release, Parameter, Release};
+
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(test: &str) -> Dir {
+            let dir = env::temp_dir().join(format!("shim-release-cache-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Dir(dir)
+        }
+
+        fn write(&self, name: &str, text: &str) -> String {
+            fs::write(self.0.join(name), text).unwrap();
+            self.0.join(name).to_string_lossy().to_string()
+        }
+
+        // release files with cache under this directory
+        fn run(&self, p: Parameter, files: &[&str]) -> Release {
+            let mut p = p.cache(self.0.join("cache").to_string_lossy().to_string())
+                .incdir(self.0.to_string_lossy().to_string());
+            for f in files.iter() { p = p.file(self.0.join(f).to_string_lossy().to_string()); }
+
+            release(&mut p).unwrap_or_else(|e| panic!("{}", e[0]))
+        }
+
+        fn text(r: &Release, name: &str) -> String {
+            r.files.iter().find(|(k, _)| k.ends_with(name)).map(|(_, v)| v.text.clone()).unwrap()
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    #[test]
+    fn include_change_invalidate_facts() {
+        let d = Dir::new("include");
+        d.write("defs.svh", "module inc_a; endmodule\n");
+        d.write("top.sv", "`include \"defs.svh\"\nmodule top; endmodule\n");
+
+        let r = d.run(Parameter::new().top("top"), &["top.sv"]);
+        assert!(r.analysis.decl_map.contains_key("inc_a"));
+
+        d.write("defs.svh", "module inc_b; endmodule\n");
+        let r = d.run(Parameter::new().top("top"), &["top.sv"]);
+        assert!(r.analysis.decl_map.contains_key("inc_b") && !r.analysis.decl_map.contains_key("inc_a"));
+        assert!(Dir::text(&r, "top.sv").contains("module inc_b_"));
+    }
+
+    #[test]
+    fn child_change_re_emit_parent() {
+        let d = Dir::new("child");
+        d.write("top.sv", "module mid; leaf u (); endmodule\nmodule top; mid m (); endmodule\n");
+        d.write("leaf.sv", "module leaf; endmodule\n");
+
+        let a = d.run(Parameter::new().top("top"), &["top.sv", "leaf.sv"]);
+        d.write("leaf.sv", "module leaf; wire w; endmodule\n");
+        let b = d.run(Parameter::new().top("top"), &["top.sv", "leaf.sv"]);
+
+        assert_ne!(a.renames["mid"], b.renames["mid"]);
+        assert!(Dir::text(&b, "top.sv").contains(&format!("module {};", b.renames["mid"])));
+        assert!(Dir::text(&b, "top.sv").contains(&format!("{} u ();", b.renames["leaf"])));
+    }
+
+    #[test]
+    fn template_and_mode_miss_output() {
+        let d = Dir::new("mode");
+        d.write("a.sv", "// note\nmodule leaf; endmodule\nmodule top; leaf u (); endmodule\n");
+
+        let r = d.run(Parameter::new().top("top"), &["a.sv"]);
+        assert!(Dir::text(&r, "a.sv").contains("// note"));
+
+        let r = d.run(Parameter::new().top("top").strip_comments(false), &["a.sv"]);
+        assert!(!Dir::text(&r, "a.sv").contains("// note"));
+
+        let r = d.run(Parameter::new().top("top").templates("{name}_r{rev}", "{name}_x{hash}"), &["a.sv"]);
+        assert!(Dir::text(&r, "a.sv").contains("module leaf_x"));
+    }
+
+    #[test]
+    fn evict_stale_entries() {
+        let d = Dir::new("evict");
+        let old = d.write("old.facts", "{}");
+        let new = d.write("new.out", "{}");
+        let other = d.write("keep.txt", "");
+
+        let past = SystemTime::now() - MAX_AGE - Duration::from_secs(60);
+        for f in [&old, &other] {
+            fs::File::options().append(true).open(f).unwrap().set_modified(past).unwrap();
+        }
+
+        evict(&d.0);
+        assert!(!PathBuf::from(&old).exists());
+        assert!(PathBuf::from(&new).exists() && PathBuf::from(&other).exists());
+    }
+}
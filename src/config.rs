@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeMap;
+use toml::{Table, Value};
+
+use crate::cache::CACHE_DIR_DEFAULT;
+use crate::error::Error;
+use crate::param::Parameter;
+
+pub const CONFIG_DEFAULT: &str = "shim-release.toml";
+
+// config key & command line option
+const FLAGS: &[(&str, &str)] = &[
+    ("auto_top", "--auto-top"),
+    ("rename_file", "--rename-file"),
+    ("prune", "--prune"),
+    ("define_report", "--define-report"),
+    ("force", "--force"),
+    ("strip_comments", "--strip-comments"),
+    ("keep_lines", "--keep-lines"),
+    ("keep_license_header", "--keep-license-header"),
+    ("minify", "--minify"),
+    ("preprocessed", "--preprocessed"),
+    ("preserve_directives", "--preserve-directives"),
+    ("no_cache", "--no-cache"),
+    ("legacy_hash", "--legacy-hash"),
+    ("content_addressed", "--content-addressed"),
+];
+
+const STRINGS: &[(&str, &str)] = &[
+    ("package", "-p"),
+    ("hash", "--hash"),
+    ("top_template", "--top-template"),
+    ("module_template", "--module-template"),
+];
+
+// relative to config file directory
+const PATHS: &[(&str, &str)] = &[
+    ("out_dir", "-o"),
+    ("manifest", "--manifest"),
+    ("against", "--against"),
+    ("stub", "--stub"),
+    ("stub_header", "--stub-header"),
+    ("cache_dir", "--cache-dir"),
+];
+
+const INTEGERS: &[(&str, &str)] = &[
+    ("revision", "-r"),
+    ("expect_tops", "--expect-tops"),
+    ("hash_width", "--hash-width"),
+    ("jobs", "-j"),
+];
+
+const LISTS: &[(&str, &str)] = &[
+    ("top", "-t"),
+    ("blackbox", "-b"),
+];
+
+// keys in table flattened as "table.key", e.g. "defines.X"
+fn parse_toml(s: &str) -> Result<BTreeMap<String, Value>, String> {
+    fn flatten(prefix: &str, t: Table, res: &mut BTreeMap<String, Value>) {
+        for (k, v) in t.into_iter() {
+            let k = if prefix.is_empty() { k } else { format!("{}.{}", prefix, k) };
+            match v {
+                Value::Table(x) => flatten(&k, x, res),
+                x => { res.insert(k, x); }
+            }
+        }
+    }
+
+    let mut res: BTreeMap<String, Value> = BTreeMap::new();
+    flatten("", s.parse::<Table>().map_err(|e| e.to_string().trim_end().to_string())?, &mut res);
+
+    Ok(res)
+}
+
+fn find<'a>(table: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, o)| *o)
+}
+
+// single string accepted as list of one
+fn strings(k: &str, v: Value) -> Result<Vec<String>, String> {
+    match v {
+        Value::String(s) => Ok(vec![s]),
+        Value::Array(a) => a.into_iter().map(|x| match x {
+            Value::String(s) => Ok(s),
+            _ => Err(format!("'{}' should be list of string", k)),
+        }).collect(),
+        _ => Err(format!("'{}' should be string or list of string", k)),
+    }
+}
+
+fn to_args(map: BTreeMap<String, Value>, base: &Path) -> Result<Vec<String>, String> {
+    let mut res: Vec<String> = Vec::new();
+    let path = |s: &str| crate::args::rebase(base, &crate::args::expand_env(s));
+
+    for (k, v) in map.into_iter() {
+        if let Some(d) = k.strip_prefix("defines.") {
+            match v {
+                Value::Boolean(true)  => res.push(format!("+define+{}", d)),
+                Value::Boolean(false) => (),
+                Value::String(s)      => res.push(format!("+define+{}={}", d, s)),
+                Value::Integer(n)     => res.push(format!("+define+{}={}", d, n)),
+                _ => return Err(format!("define '{}' should be boolean, string or integer", d)),
+            }
+        }
+        else if let Some(o) = find(FLAGS, &k) {
+            match v {
+                Value::Boolean(true)  => res.push(o.to_string()),
+                Value::Boolean(false) => (),
+                _ => return Err(format!("'{}' should be boolean", k)),
+            }
+        }
+        else if let Some(o) = find(STRINGS, &k) {
+            match v {
+                Value::String(s) => res.extend([o.to_string(), s]),
+                _ => return Err(format!("'{}' should be string", k)),
+            }
+        }
+        else if let Some(o) = find(PATHS, &k) {
+            match v {
+                Value::String(s) => res.extend([o.to_string(), path(&s)]),
+                _ => return Err(format!("'{}' should be string", k)),
+            }
+        }
+        else if let Some(o) = find(INTEGERS, &k) {
+            match v {
+                Value::Integer(n) if n >= 0 => res.extend([o.to_string(), n.to_string()]),
+                _ => return Err(format!("'{}' should be non-negative integer", k)),
+            }
+        }
+        else if let Some(o) = find(LISTS, &k) {
+            for s in strings(&k, v)?.into_iter() { res.extend([o.to_string(), s]); }
+        }
+        else {
+            match k.as_str() {
+                "files"     => res.extend(strings(&k, v)?.iter().map(|s| path(s))),
+                "filelists" => for s in strings(&k, v)?.iter() { res.extend(["-F".to_string(), path(s)]); },
+                "incdirs"   => res.extend(strings(&k, v)?.iter().map(|s| format!("+incdir+{}", path(s)))),
+                _ => return Err(format!("unknown key '{}'", k)),
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// config file as argument list, placed before command line so the latter override it
+pub(crate) fn read_config(path: &str) -> Result<Vec<String>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| Error::Io(path.to_string(), e))?;
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    parse_toml(&content)
+        .and_then(|m| to_args(m, base))
+        .map_err(|e| Error::Arg(format!("{}: {}", path, e)))
+}
+
+fn toml_str(s: &str) -> String {
+    Value::String(s.to_string()).to_string()
+}
+
+fn toml_key(k: &str) -> String {
+    if !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') { k.to_string() }
+    else { toml_str(k) }
+}
+
+fn toml_list<'a, I: Iterator<Item = &'a String>>(iter: I) -> String {
+    let items: Vec<String> = iter.map(|x| format!("  {},\n", toml_str(x))).collect();
+    if items.is_empty() { "[]".to_string() } else { format!("[\n{}]", items.concat()) }
+}
+
+// effective settings in config format, command line merged
+pub fn dump_config(p: &Parameter) -> String {
+    let mut s = String::from("# effective settings of shim-release\n");
+
+    s.push_str(&format!("package = {}\n", toml_str(&p.pkg)));
+    s.push_str(&format!("revision = {}\n", p.rev));
+    s.push_str(&format!("files = {}\n", toml_list(p.file_list.iter())));
+    s.push_str(&format!("incdirs = {}\n", toml_list(p.inc_list.iter())));
+    s.push_str(&format!("top = {}\n", toml_list(p.top_set.iter().chain(p.top_patterns.iter()))));
+    s.push_str(&format!("blackbox = {}\n", toml_list(p.bb_set.iter().chain(p.bb_patterns.iter()))));
+
+    if let Some(n) = p.expect_tops { s.push_str(&format!("expect_tops = {}\n", n)); }
+
+    // default cache dir is relative to working directory, re-based once written to config
+    let cache_dir = p.cache_dir.clone().filter(|d| d != CACHE_DIR_DEFAULT);
+    let paths = [("out_dir", &p.out_dir), ("manifest", &p.manifest), ("against", &p.against),
+                 ("stub", &p.stub), ("stub_header", &p.stub_header), ("cache_dir", &cache_dir)];
+    for (k, v) in paths.iter() {
+        if let Some(x) = v { s.push_str(&format!("{} = {}\n", k, toml_str(x))); }
+    }
+
+    let flags = [p.auto_top, p.rename_file, p.prune, p.define_report, p.force, p.strip_comments,
+                 p.keep_lines, p.keep_header, p.minify, p.preprocessed, p.preserve_directives, p.cache_dir.is_none(),
+                 p.legacy_hash, p.content_addressed];
+    for ((k, _), v) in FLAGS.iter().zip(flags.iter()) {
+        s.push_str(&format!("{} = {}\n", k, v));
+    }
+
+    s.push_str(&format!("hash = {}\n", toml_str(p.hash.name())));
+    if let Some(w) = p.hash_width { s.push_str(&format!("hash_width = {}\n", w)); }
+    if let Some(n) = p.jobs { s.push_str(&format!("jobs = {}\n", n)); }
+    s.push_str(&format!("top_template = {}\n", toml_str(&p.top_template)));
+    s.push_str(&format!("module_template = {}\n", toml_str(&p.module_template)));
+
+    s.push_str("\n[defines]\n");
+    for (k, v) in p.defines.iter() {
+        match v {
+            None     => s.push_str(&format!("{} = true\n", toml_key(k))),
+            Some(v1) => s.push_str(&format!("{} = {}\n", toml_key(k), toml_str(v1))),
+        }
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::path::Path;
+    use super::{dump_config, parse_toml, to_args};
+    use crate::args::parse_args;
+
+    #[test]
+    fn toml_values() {
+        let m = parse_toml("a = \"x\\t\"  # comment\nb = 'C:\\dir'\nc = 1_000\n\
+                            d = [\n  \"p\",  # one\n  'q',\n]\ne.f = false\n[t]\n\"k.x\" = true\n").unwrap();
+
+        assert_eq!(m["a"].as_str(), Some("x\t"));
+        assert_eq!(m["b"].as_str(), Some("C:\\dir"));
+        assert_eq!(m["c"].as_integer(), Some(1000));
+        assert_eq!(m["d"].as_array().map(|v| v.len()), Some(2));
+        assert_eq!(m["e.f"].as_bool(), Some(false));
+        assert_eq!(m["t.k.x"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn toml_errors() {
+        let err = |s: &str| parse_toml(s).err().unwrap();
+
+        assert!(err("a = 1\nb = \"open\n").contains("line 2"));
+        assert!(err("a = 1\na = 2\n").contains("line 2"));
+        assert!(to_args(parse_toml("top = 1.5\n").unwrap(), Path::new("")).is_err());
+        assert!(to_args(parse_toml("[defines]\nA = [1]\n").unwrap(), Path::new("")).is_err());
+        assert!(to_args(parse_toml("x = 1\n").unwrap(), Path::new("")).unwrap_err().contains("unknown key 'x'"));
+    }
+
+    // every setting survive dump & read back
+    #[test]
+    fn dump_config_round_trip() {
+        let dir = env::temp_dir().join(format!("shim-release-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let d = dir.to_string_lossy().to_string();
+
+        let args: Vec<String> = [
+            "-p", "soc \"x\"", "-r", "7", "-t", "top", "-t", "re:^tb_", "-b", "bb*", "--auto-top", "--expect-tops", "3",
+            "+define+A", "+define+B=http://x y", "+incdir+/inc", "-o", "/out", "--manifest", "/m.json",
+            "--stub", "/s.sv", "--prune", "--strip-comments", "--keep-license-header", "--preprocessed",
+            "--hash", "sha256", "--hash-width", "12", "--legacy-hash", "-j", "4", "--no-cache",
+            "--top-template", "{name}_{pkg}_r{rev}", "/a.sv", "/b\tc.sv",
+        ].iter().map(|x| x.to_string()).collect();
+
+        let p = parse_args(args).unwrap();
+
+        let cfg = dir.join("c.toml");
+        fs::write(&cfg, dump_config(&p)).unwrap();
+        let q = parse_args(vec!["--config".to_string(), cfg.to_string_lossy().to_string()]).unwrap();
+        fs::remove_dir_all(&d).unwrap();
+
+        assert_eq!(p, q);
+    }
+
+    #[test]
+    fn dump_config_omit_default_cache_dir() {
+        let p = parse_args(vec!["a.sv".to_string()]).unwrap();
+        assert!(!dump_config(&p).contains("cache_dir"));
+        assert!(dump_config(&p).contains("no_cache = false"));
+
+        let p = parse_args(["--cache-dir", "/c", "a.sv"].iter().map(|x| x.to_string()).collect()).unwrap();
+        assert!(dump_config(&p).contains("cache_dir = \"/c\""));
+    }
+}
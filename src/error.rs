@@ -0,0 +1,82 @@
+use std::{fmt, fs, io};
+use std::path::Path;
+use sv_parser::Error as SvError;
+
+#[derive(Debug)]
+pub enum Error {
+    Arg(String),
+    Io(String, io::Error),
+    Parse {
+        file: String,
+        // line & column, both start from 1
+        pos: Option<(usize, usize)>,
+        excerpt: String,
+        msg: String,
+    },
+    Rewrite(String),
+    Output(String),
+    Verify(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Arg(_)      => 2,
+            Error::Io(_, _)    => 3,
+            Error::Parse { .. } => 4,
+            Error::Rewrite(_)  => 5,
+            Error::Output(_)   => 6,
+            Error::Verify(_)   => 7,
+        }
+    }
+
+    pub(crate) fn parse_at(file: &Path, offset: usize, msg: &str) -> Error {
+        let file_str = file.to_string_lossy().to_string();
+
+        let text = match fs::read_to_string(file) {
+            Ok(x) => x,
+            Err(_) => return Error::Parse { file: file_str, pos: None, excerpt: String::new(), msg: msg.to_string() },
+        };
+
+        let offset = offset.min(text.len());
+        let begin = text[..offset].rfind('\n').map(|x| x + 1).unwrap_or(0);
+        let end = text[offset..].find('\n').map(|x| x + offset).unwrap_or(text.len());
+
+        let line = text[..begin].matches('\n').count() + 1;
+        let column = text[begin..offset].chars().count() + 1;
+
+        let src = text[begin..end].trim_end();
+        let indent = format!("{}", line).len();
+        let excerpt = format!("{} | {}\n{} | {}^", line, src, " ".repeat(indent), " ".repeat(column - 1));
+
+        Error::Parse { file: file_str, pos: Some((line, column)), excerpt, msg: msg.to_string() }
+    }
+
+    pub(crate) fn from_sv(file: &str, e: SvError) -> Error {
+        match e {
+            SvError::Include { source }       => Error::from_sv(file, *source),
+            SvError::File { source, path }    => Error::Io(path.to_string_lossy().to_string(), source),
+            SvError::Io(x)                    => Error::Io(file.to_string(), x),
+            SvError::Parse(Some((path, pos))) => Error::parse_at(&path, pos, "syntax error"),
+            x => Error::Parse { file: file.to_string(), pos: None, excerpt: String::new(), msg: x.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Arg(m)     => write!(f, "{}", m),
+            Error::Io(p, e)   => write!(f, "{}: {}", p, e),
+            Error::Parse { file, pos: None, msg, .. } => write!(f, "{}: {}", file, msg),
+            Error::Parse { file, pos: Some((l, c)), excerpt, msg } => write!(f, "{}:{}:{}: {}\n{}", file, l, c, msg, excerpt),
+            Error::Rewrite(m) => write!(f, "{}", m),
+            Error::Output(m)  => write!(f, "{}", m),
+            Error::Verify(m)  => write!(f, "{}", m),
+        }
+    }
+}
+
+impl From<Error> for Vec<Error> {
+    fn from(e: Error) -> Vec<Error> { vec![e] }
+}
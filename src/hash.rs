@@ -0,0 +1,170 @@
+use std::fmt;
+use crc::{Crc, Digest, CRC_32_CKSUM, CRC_64_XZ};
+use sha2::{Digest as _, Sha256};
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+static CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_XZ);
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HashAlgo {
+    Crc32,
+    Crc64,
+    Sha256,
+}
+
+impl HashAlgo {
+    pub fn from_name(s: &str) -> Option<HashAlgo> {
+        match s {
+            "crc32"  => Some(HashAlgo::Crc32),
+            "crc64"  => Some(HashAlgo::Crc64),
+            "sha256" => Some(HashAlgo::Sha256),
+            _        => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgo::Crc32  => "crc32",
+            HashAlgo::Crc64  => "crc64",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+
+    // hex characters of full checksum
+    pub fn max_width(&self) -> usize {
+        match self {
+            HashAlgo::Crc32  => 8,
+            HashAlgo::Crc64  => 16,
+            HashAlgo::Sha256 => 64,
+        }
+    }
+
+    // suffix width when not given
+    pub fn default_width(&self) -> usize {
+        match self {
+            HashAlgo::Sha256 => 16,
+            x                => x.max_width(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub enum Checksum {
+    Crc32(u32),
+    Crc64(u64),
+    Sha256([u8; 32]),
+}
+
+impl Checksum {
+    // bytes fold into digest of parent module
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Checksum::Crc32(x)  => x.to_le_bytes().to_vec(),
+            Checksum::Crc64(x)  => x.to_le_bytes().to_vec(),
+            Checksum::Sha256(x) => x.to_vec(),
+        }
+    }
+
+    // leading `width` hex characters
+    pub fn suffix(&self, width: usize) -> String {
+        let s = self.to_string();
+        s[..width.min(s.len())].to_string()
+    }
+
+    // inverse of Display, e.g. checksum read from cache
+    pub(crate) fn from_hex(algo: HashAlgo, s: &str) -> Option<Checksum> {
+        if s.len() != algo.max_width() || !s.chars().all(|c| c.is_ascii_hexdigit()) { return None; }
+
+        match algo {
+            HashAlgo::Crc32  => u32::from_str_radix(s, 16).ok().map(Checksum::Crc32),
+            HashAlgo::Crc64  => u64::from_str_radix(s, 16).ok().map(Checksum::Crc64),
+            HashAlgo::Sha256 => {
+                let mut x = [0u8; 32];
+                for (i, b) in x.iter_mut().enumerate() {
+                    *b = u8::from_str_radix(&s[i*2..i*2+2], 16).ok()?;
+                }
+                Some(Checksum::Sha256(x))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::Crc32(x)  => write!(f, "{:08x}", x),
+            Checksum::Crc64(x)  => write!(f, "{:016x}", x),
+            Checksum::Sha256(x) => {
+                for b in x.iter() { write!(f, "{:02x}", b)?; }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) enum Hasher {
+    Crc32(Digest<'static, u32>),
+    Crc64(Digest<'static, u64>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn new(algo: HashAlgo) -> Hasher {
+        match algo {
+            HashAlgo::Crc32  => Hasher::Crc32(CRC32.digest()),
+            HashAlgo::Crc64  => Hasher::Crc64(CRC64.digest()),
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Crc32(d)  => d.update(bytes),
+            Hasher::Crc64(d)  => d.update(bytes),
+            Hasher::Sha256(d) => d.update(bytes),
+        }
+    }
+
+    pub(crate) fn update_checksum(&mut self, c: &Checksum) {
+        self.update(&c.to_bytes());
+    }
+
+    pub(crate) fn finalize(self) -> Checksum {
+        match self {
+            Hasher::Crc32(d)  => Checksum::Crc32(d.finalize()),
+            Hasher::Crc64(d)  => Checksum::Crc64(d.finalize()),
+            Hasher::Sha256(d) => Checksum::Sha256(d.finalize().into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checksum, HashAlgo, Hasher};
+
+    fn sha256(bytes: &[u8]) -> String {
+        let mut h = Hasher::new(HashAlgo::Sha256);
+        h.update(bytes);
+        h.finalize().to_string()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn checksum_hex_round_trip() {
+        for algo in [HashAlgo::Crc32, HashAlgo::Crc64, HashAlgo::Sha256] {
+            let mut h = Hasher::new(algo);
+            h.update(b"module m; endmodule");
+            let c = h.finalize();
+
+            assert_eq!(Checksum::from_hex(algo, &c.to_string()), Some(c));
+        }
+
+        assert_eq!(Checksum::from_hex(HashAlgo::Crc32, "xyz01234"), None);
+        assert_eq!(Checksum::from_hex(HashAlgo::Crc32, "0123"), None);
+    }
+}
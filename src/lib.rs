@@ -0,0 +1,162 @@
+// Send check of sv-parser syntax tree when parsing in threads
+#![recursion_limit = "256"]
+
+use std::{sync::mpsc, thread};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeMap, HashMap};
+use log::info;
+use sv_parser::{parse_sv, Define, DefineText, Defines, SyntaxTree};
+
+mod args;
+mod cache;
+mod config;
+mod error;
+mod hash;
+mod multi;
+mod output;
+mod param;
+mod pattern;
+mod report;
+mod rewrite;
+mod stub;
+mod template;
+mod verify;
+
+pub use args::parse_args;
+pub use cache::{Cache, CACHE_DIR_DEFAULT};
+pub use config::{dump_config, CONFIG_DEFAULT};
+pub use error::Error;
+pub use hash::{Checksum, HashAlgo};
+pub use multi::{multi, parse_multi, SHARED_FILE};
+pub use output::{write_manifest, write_output};
+pub use param::{show_info, Parameter, PKG_DEFAULT, REV_DEFAULT};
+pub use pattern::resolve_patterns;
+pub use report::define_report;
+pub use rewrite::{analyze, auto_top, rewrite, unreachable, Analysis, Decl, Output};
+pub use stub::{write_stubs, PortUse};
+pub use template::{MODULE_TEMPLATE_DEFAULT, TOP_TEMPLATE_DEFAULT};
+pub use verify::verify;
+
+use rewrite::new_name;
+
+fn to_defines(defs: &BTreeMap<String, Option<String>>) -> Defines {
+    let mut res: Defines = HashMap::new();
+
+    for (k, v) in defs.iter() {
+        let v1 = match v {
+            None => Define {
+                identifier: k.clone(),
+                arguments: vec![],
+                text: None
+            },
+            Some(x) => Define {
+                identifier: k.clone(),
+                arguments: vec![],
+                text: Some(DefineText {
+                    text: x.to_string(),
+                    origin: None
+                })
+            }
+        };
+
+        res.insert(k.to_string(), Some(v1));
+    }
+
+    res
+}
+
+
+// stack of parser thread, deeply nested expression recurse a lot
+const PARSE_STACK_SIZE: usize = 64 << 20;
+
+// files with 1st pass result in cache are skipped
+pub fn parse_files(p: &Parameter, cache: &Cache) -> Result<BTreeMap<String, SyntaxTree>, Vec<Error>> {
+    info!("parse files, jobs {}", p.job_count());
+
+    let files: Vec<String> = p.file_list.iter().filter(|f| cache.facts(f).is_none()).cloned().collect();
+    parse_list(p, &files)
+}
+
+pub(crate) fn parse_list(p: &Parameter, files: &[String]) -> Result<BTreeMap<String, SyntaxTree>, Vec<Error>> {
+    let jobs = p.job_count().min(files.len()).max(1);
+
+    let mut res: BTreeMap<String, SyntaxTree> = BTreeMap::new();
+    let mut errs: Vec<Error> = Vec::new();
+
+    let defines = to_defines(&p.defines);
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel::<(usize, Result<SyntaxTree, Error>)>();
+
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            let (tx, next, defines) = (tx.clone(), &next, &defines);
+
+            thread::Builder::new().stack_size(PARSE_STACK_SIZE).spawn_scoped(s, move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let file = match files.get(i) {
+                        Some(x) => x,
+                        None => break,
+                    };
+
+                    let r = parse_sv(file, defines, &p.inc_list, false, false)
+                        .map(|(x, _)| x)
+                        .map_err(|e| Error::from_sv(file, e));
+
+                    if tx.send((i, r)).is_err() { break; }
+                }
+            }).expect("fail to spawn parser thread");
+        }
+
+        drop(tx);
+
+        // log & collect in file list order, independent of thread scheduling
+        let mut pending: BTreeMap<usize, Result<SyntaxTree, Error>> = BTreeMap::new();
+        let mut done = 0;
+
+        for (i, r) in rx.iter() {
+            pending.insert(i, r);
+
+            while let Some(r) = pending.remove(&done) {
+                let file = &files[done];
+                info!("  parsing {} ...", file);
+
+                match r {
+                    Ok(syntax_tree) => { res.insert(file.to_string(), syntax_tree); },
+                    Err(e) => errs.push(e),
+                }
+
+                done += 1;
+            }
+        }
+    });
+
+    if errs.is_empty() { Ok(res) } else { Err(errs) }
+}
+
+// rename result of one run, nothing written to file system
+pub struct Release {
+    // original name -> new name of every declared module, package, etc.
+    pub renames: BTreeMap<String, String>,
+    // input path -> rewritten text & first module for --rename-file, pruned file left out
+    pub files: BTreeMap<String, Output>,
+    pub analysis: Analysis,
+}
+
+// top patterns & auto top resolved into `p`, as needed by write_output, write_manifest & write_stubs
+pub fn release(p: &mut Parameter) -> Result<Release, Vec<Error>> {
+    let mut cache = Cache::open(p);
+    let syntax_tree_map = parse_files(p, &cache)?;
+    let analysis = analyze(p, &syntax_tree_map, &mut cache)?;
+    resolve_patterns(p, &analysis)?;
+    auto_top(p, &analysis)?;
+    define_report(p, &analysis);
+
+    let files = rewrite(p, &syntax_tree_map, &analysis, &cache)?;
+
+    let renames = analysis.module_map.keys()
+        .filter_map(|m| new_name(p, &analysis.module_map, m).map(|n| (m.clone(), n)))
+        .collect();
+
+    Ok(Release { renames, files, analysis })
+}
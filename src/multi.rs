@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use log::info;
+use sv_parser::SyntaxTree;
+
+use crate::args::parse_args;
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::hash::Checksum;
+use crate::output::{destinations, write_manifest, write_output};
+use crate::param::{show_info, Parameter};
+use crate::pattern::resolve_patterns;
+use crate::rewrite::{analyze, auto_top, new_name, rewrite_except, Analysis, Output};
+use crate::stub::write_stubs;
+use crate::parse_files;
+
+// module identical in several packages, written once to output directory
+pub const SHARED_FILE: &str = "shared.sv";
+
+struct Group {
+    p: Parameter,
+    st_map: BTreeMap<String, SyntaxTree>,
+    a: Analysis,
+    cache: Cache,
+}
+
+// options before 1st --group apply to every package, output, manifest & stub go to out/<pkg>/, e.g.
+// `-o out --group -p a -r 1 -t top a.sv --group -p b -r 3 -t top b.sv`
+pub fn parse_multi(args: Vec<String>) -> Result<Vec<Parameter>, Error> {
+    let mut parts = args.split(|x| x == "--group");
+    let mut common: Vec<String> = parts.next().unwrap_or_default().to_vec();
+
+    // identical module get same digest only when not salted by pkg & rev
+    common.push("--content-addressed".to_string());
+
+    let mut res: Vec<Parameter> = Vec::new();
+
+    for g in parts {
+        let mut args = common.clone();
+        args.extend(g.iter().cloned());
+        res.push(parse_args(args)?);
+    }
+
+    if res.len() < 2 { return Err(Error::Arg("multi need two or more --group".to_string())); }
+
+    let out_dir = &res[0].out_dir;
+    if out_dir.is_none() || res.iter().any(|p| p.out_dir != *out_dir) {
+        return Err(Error::Arg("multi need one -o before the first --group".to_string()));
+    }
+
+    let mut pkgs: BTreeSet<&String> = BTreeSet::new();
+    for p in res.iter() {
+        if !pkgs.insert(&p.pkg) { return Err(Error::Arg(format!("package {} given by more than one group", p.pkg))); }
+    }
+
+    let out_dir = PathBuf::from(out_dir.clone().unwrap());
+    per_package(&mut res, &out_dir, |p| &mut p.manifest);
+    per_package(&mut res, &out_dir, |p| &mut p.stub);
+
+    Ok(res)
+}
+
+// file given to more than one group, e.g. before 1st --group, written under out_dir/<pkg>/ like output
+fn per_package(res: &mut [Parameter], out_dir: &Path, field: fn(&mut Parameter) -> &mut Option<String>) {
+    let mut count: BTreeMap<String, usize> = BTreeMap::new();
+
+    for p in res.iter_mut() {
+        if let Some(x) = field(p) { *count.entry(x.clone()).or_default() += 1; }
+    }
+
+    for p in res.iter_mut() {
+        let dir = out_dir.join(&p.pkg);
+
+        if let Some(x) = field(p).as_mut().filter(|x| count[x.as_str()] > 1) {
+            let name = Path::new(x.as_str()).file_name().map(PathBuf::from).unwrap_or_default();
+            *x = dir.join(name).to_string_lossy().to_string();
+        }
+    }
+}
+
+// non-top module with same new name & checksum in two or more packages -> packages declaring it
+fn find_shared(groups: &[Group]) -> Result<BTreeMap<String, Vec<usize>>, Error> {
+    let mut seen: BTreeMap<String, (&Checksum, Vec<usize>)> = BTreeMap::new();
+
+    for (i, g) in groups.iter().enumerate() {
+        for (m, cksum) in g.a.module_map.iter().filter(|(m, _)| !g.p.top_set.contains(*m)) {
+            let n = match new_name(&g.p, &g.a.module_map, m) {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let e = seen.entry(n.clone()).or_insert((cksum, Vec::new()));
+
+            if e.0 != cksum {
+                return Err(Error::Rewrite(format!("{} renamed to {} in package {} and {} with different content, \
+                                                   use wider --hash-width or other --hash",
+                                                  m, n.trim_end(), groups[e.1[0]].p.pkg, g.p.pkg)));
+            }
+
+            e.1.push(i);
+        }
+    }
+
+    Ok(seen.into_iter().filter(|(_, (_, v))| v.len() > 1).map(|(n, (_, v))| (n, v)).collect())
+}
+
+pub fn multi(groups: Vec<Parameter>) -> Result<(), Vec<Error>> {
+    let out_dir = match groups.first().and_then(|p| p.out_dir.clone()) {
+        Some(d) => PathBuf::from(d),
+        None => return Err(Error::Arg("multi need output directory".to_string()).into()),
+    };
+
+    let mut done: Vec<Group> = Vec::new();
+
+    for mut p in groups.into_iter() {
+        show_info(&p);
+
+        let mut cache = Cache::open(&p);
+        let st_map = parse_files(&p, &cache)?;
+        let a = analyze(&p, &st_map, &mut cache)?;
+        resolve_patterns(&mut p, &a)?;
+        auto_top(&mut p, &a)?;
+
+        done.push(Group { p, st_map, a, cache });
+    }
+
+    let shared = find_shared(&done)?;
+
+    info!("shared modules:");
+    for (n, v) in shared.iter() {
+        let pkgs: Vec<&str> = v.iter().map(|i| done[*i].p.pkg.as_str()).collect();
+        info!("  {} ({})", n.trim_end(), pkgs.join(", "));
+    }
+
+    let path = out_dir.join(SHARED_FILE);
+
+    // rewrite every package first, nothing written until all destinations are checked
+    let mut plan: Vec<(Parameter, BTreeMap<String, Output>)> = Vec::new();
+    let mut text = String::new();
+
+    for (i, g) in done.iter().enumerate() {
+        info!("package {}", g.p.pkg);
+
+        // declared one in shared file, first package holding it as its origin
+        let omit: BTreeSet<String> = g.a.module_map.keys()
+            .filter(|m| !g.p.top_set.contains(*m))
+            .filter(|m| new_name(&g.p, &g.a.module_map, m).map(|n| shared.contains_key(&n)).unwrap_or(false))
+            .cloned()
+            .collect();
+
+        let own: BTreeSet<&String> = omit.iter()
+            .filter(|m| new_name(&g.p, &g.a.module_map, m).map(|n| shared[&n][0] == i).unwrap_or(false))
+            .collect();
+
+        let mut p = g.p.clone();
+        p.out_dir = Some(out_dir.join(&p.pkg).to_string_lossy().to_string());
+
+        let out_map = rewrite_except(&p, &g.st_map, &g.a, &g.cache, &omit)?;
+
+        if !own.is_empty() {
+            // everything else left out, pruning not applied as other package may still need it
+            info!("package {}, shared part", g.p.pkg);
+            let rest: BTreeSet<String> = g.a.module_map.keys().filter(|m| !own.contains(m)).cloned().collect();
+            let mut q = p.clone();
+            q.prune = false;
+
+            for (path, o) in rewrite_except(&q, &g.st_map, &g.a, &g.cache, &rest)?.iter() {
+                text.push_str(&format!("\n// {}: {}\n", g.p.pkg, path));
+                text.push_str(&o.text);
+            }
+        }
+
+        plan.push((p, out_map));
+    }
+
+    check_plan(&plan, (!shared.is_empty()).then_some(&path))?;
+
+    for ((p, out_map), g) in plan.iter().zip(done.iter()) {
+        write_output(p, out_map)?;
+
+        for f in [&p.manifest, &p.stub].into_iter().flatten() {
+            if let Some(d) = Path::new(f).parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(d).map_err(|e| Error::Io(d.display().to_string(), e))?;
+            }
+        }
+
+        write_manifest(p, &g.a)?;
+        write_stubs(p, &g.a)?;
+    }
+
+    if shared.is_empty() {
+        info!("no module shared by packages");
+        return Ok(());
+    }
+
+    info!("write shared {}", path.display());
+
+    let pkgs: Vec<&str> = done.iter().map(|g| g.p.pkg.as_str()).collect();
+    let text = format!("// modules shared by packages {}\n{}", pkgs.join(", "), text);
+
+    fs::create_dir_all(&out_dir)
+        .and_then(|_| fs::write(&path, text))
+        .map_err(|e| Error::Io(path.display().to_string(), e).into())
+}
+
+// every file to write has one writer, and existing one is only overwritten with --force
+fn check_plan(plan: &[(Parameter, BTreeMap<String, Output>)], shared: Option<&PathBuf>) -> Result<(), Vec<Error>> {
+    let mut errs: Vec<Error> = Vec::new();
+    let mut taken: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut claim = |f: PathBuf, no_overwrite: bool, errs: &mut Vec<Error>| {
+        if no_overwrite && f.exists() {
+            errs.push(Error::Output(format!("{} already exist, use --force to overwrite", f.display())));
+        }
+
+        if !taken.insert(f.clone()) {
+            errs.push(Error::Output(format!("{} written more than once", f.display())));
+        }
+    };
+
+    for (p, out_map) in plan.iter() {
+        let out_dir = PathBuf::from(p.out_dir.as_ref().unwrap());
+
+        // existence of output checked by destinations, manifest overwritten as in single package
+        match destinations(p, &out_dir, out_map) {
+            Ok(dest_map) => for f in dest_map.into_keys() { claim(f, false, &mut errs); },
+            Err(e) => errs.extend(e),
+        }
+
+        if let Some(f) = &p.manifest { claim(PathBuf::from(f), false, &mut errs); }
+        if let Some(f) = &p.stub { claim(PathBuf::from(f), !p.force, &mut errs); }
+    }
+
+    if let Some(f) = shared { claim(f.clone(), !plan[0].0.force, &mut errs); }
+
+    if errs.is_empty() { Ok(()) } else { Err(errs) }
+}
@@ -0,0 +1,257 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::collections::BTreeMap;
+use log::info;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use crate::param::Parameter;
+use crate::rewrite::{new_name, Analysis, Output};
+
+pub fn write_manifest(p: &Parameter, r: &Analysis) -> Result<(), Error> {
+    let path = match &p.manifest {
+        Some(_) if p.verify => return Ok(()),
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    // declared modules first, then referenced but undeclared ones
+    let names = r.module_map.keys().chain(r.module_ref.iter().filter(|m| !r.module_map.contains_key(*m)));
+
+    let modules: Vec<Value> = names.map(|m| {
+        let new = new_name(p, &r.module_map, m).unwrap_or_else(|| m.to_string());
+        let decl = r.decl_map.get(m);
+        let declared = r.module_map.contains_key(m);
+
+        json!({
+            "name": m,
+            "kind": decl.map(|d| d.kind),
+            // drop terminating space of escaped identifier
+            "new_name": new.trim_end(),
+            "checksum": r.module_map.get(m).map(|c| c.to_string()),
+            "file": decl.map(|d| &d.file),
+            "line": decl.map(|d| d.line),
+            "top": p.top_set.contains(m),
+            "blackbox": p.bb_set.contains(m),
+            "unused": declared && !r.module_ref.contains(m) && !p.top_set.contains(m),
+            "unmapped": !declared && !p.bb_set.contains(m),
+        })
+    }).collect();
+
+    let manifest = json!({
+        "package": p.pkg,
+        "revision": p.rev,
+        "hash": p.hash.name(),
+        "hash_width": p.suffix_width(),
+        "token_hash": if p.legacy_hash { "legacy" } else { "canonical" },
+        "content_addressed": p.content_addressed,
+        "top_template": p.top_template,
+        "module_template": p.module_template,
+        "defines": p.defines,
+        "modules": modules,
+    });
+
+    let s = format!("{:#}\n", manifest);
+
+    info!("write manifest {}", path);
+
+    fs::write(path, s).map_err(|e| Error::Io(path.to_string(), e))
+}
+
+// longest common parent directory of all input files
+fn common_dir(paths: &[PathBuf]) -> PathBuf {
+    let mut res: Option<PathBuf> = None;
+
+    for p in paths.iter() {
+        let dir = p.parent().unwrap_or_else(|| Path::new(""));
+
+        res = Some(match res {
+            None => dir.to_path_buf(),
+            Some(r) => r.components().zip(dir.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+
+    res.unwrap_or_default()
+}
+
+pub fn write_output(p: &Parameter, out_map: &BTreeMap<String, Output>) -> Result<(), Vec<Error>> {
+    let out_dir = match &p.out_dir {
+        None => {
+            for o in out_map.values() { print!("{}", o.text); }
+            return Ok(());
+        }
+        Some(d) => PathBuf::from(d),
+    };
+
+    let dest_map = destinations(p, &out_dir, out_map)?;
+
+    info!("write output to {}", out_dir.display());
+
+    for (dest, path) in dest_map.iter() {
+        info!("  {} -> {}", path, dest.display());
+
+        if let Some(d) = dest.parent() {
+            fs::create_dir_all(d).map_err(|e| Error::Io(d.display().to_string(), e))?;
+        }
+
+        fs::write(dest, &out_map[*path].text).map_err(|e| Error::Io(dest.display().to_string(), e))?;
+    }
+
+    Ok(())
+}
+
+// destination of every output file under out_dir, decided before touching anything
+pub(crate) fn destinations<'a>(p: &Parameter,
+                               out_dir: &Path,
+                               out_map: &'a BTreeMap<String, Output>) -> Result<BTreeMap<PathBuf, &'a String>, Vec<Error>> {
+    let abs_list: Vec<PathBuf> = out_map.keys()
+        .map(|f| fs::canonicalize(f).unwrap_or_else(|_| PathBuf::from(f)))
+        .collect();
+    let base = common_dir(&abs_list);
+
+    let mut dest_map: BTreeMap<PathBuf, &String> = BTreeMap::new();
+    let mut errs: Vec<Error> = Vec::new();
+
+    for ((path, o), abs) in out_map.iter().zip(abs_list.iter()) {
+        let dest = match (&o.first_module, p.rename_file) {
+            (Some(m), true) => {
+                let name = format!("{}.sv", m.trim_start_matches('\\').trim_end());
+
+                // escaped identifier may hold `/` or `..`, file must stay directly in out_dir
+                let mut c = Path::new(&name).components();
+                if !matches!((c.next(), c.next()), (Some(Component::Normal(_)), None)) {
+                    errs.push(Error::Output(format!("{} can not be named by module {}, not a plain file name", path, m.trim_end())));
+                    continue;
+                }
+
+                out_dir.join(name)
+            }
+            _ => {
+                let rel: PathBuf = abs.strip_prefix(&base).unwrap_or(abs).components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect();
+                out_dir.join(rel)
+            }
+        };
+
+        if let Some(other) = dest_map.get(&dest) {
+            errs.push(Error::Output(format!("{} and {} both write to {}", other, path, dest.display())));
+        }
+        else if dest.exists() && !p.force {
+            errs.push(Error::Output(format!("{} already exist, use --force to overwrite", dest.display())));
+        }
+
+        dest_map.insert(dest, path);
+    }
+
+    if !errs.is_empty() { return Err(errs); }
+
+    Ok(dest_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::path::{Path, PathBuf};
+    use serde_json::Value;
+    use crate::{release, write_manifest, write_output, Error, Parameter};
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shim-release-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, text: &str) -> String {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    // layout under common directory of inputs kept, existing file only overwritten with --force
+    #[test]
+    fn mirror_and_force() {
+        let dir = temp_dir("mirror");
+        let a = write(&dir, "src/a/x.sv", "module top; leaf u (); endmodule\n");
+        let b = write(&dir, "src/b/y.sv", "module leaf; endmodule\n");
+
+        let mut p = Parameter::new().top("top").file(a).file(b);
+        p.out_dir = Some(dir.join("out").to_string_lossy().to_string());
+
+        let r = release(&mut p).unwrap_or_else(|e| panic!("{}", e[0]));
+        write_output(&p, &r.files).unwrap();
+
+        assert!(fs::read_to_string(dir.join("out/a/x.sv")).unwrap().starts_with("module top_r0;"));
+        assert!(dir.join("out/b/y.sv").exists());
+
+        let err = write_output(&p, &r.files).unwrap_err();
+        assert_eq!(err.len(), 2);
+        assert!(matches!(&err[0], Error::Output(x) if x.contains("already exist")));
+
+        p.force = true;
+        write_output(&p, &r.files).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rename_file_stay_in_out_dir() {
+        let dir = temp_dir("rename-file");
+        let a = write(&dir, "src/a.sv", "module \\a/../../x ; endmodule\n");
+
+        let mut p = Parameter::new().top("\\a/../../x ").file(a);
+        p.out_dir = Some(dir.join("out").to_string_lossy().to_string());
+        p.rename_file = true;
+
+        let r = release(&mut p).unwrap_or_else(|e| panic!("{}", e[0]));
+        let err = write_output(&p, &r.files).unwrap_err();
+        let exist = dir.join("out").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(&err[0], Error::Output(x) if x.contains("not a plain file name")));
+        assert!(!exist);
+    }
+
+    // manifest written by write_manifest read back as verify does
+    #[test]
+    fn manifest_round_trip() {
+        let dir = env::temp_dir().join(format!("shim-release-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let src = dir.join("a.sv");
+        let manifest = dir.join("m.json");
+        fs::write(&src, "module leaf; endmodule\nmodule \\top\"x ; leaf u (); bb b (); endmodule\n").unwrap();
+
+        let mut p = Parameter::new().pkg("p\"k").rev(3).top("\\top\"x ").blackbox("bb").define("D", Some("a\\b"))
+            .file(src.to_string_lossy().to_string());
+        p.manifest = Some(manifest.to_string_lossy().to_string());
+
+        let r = release(&mut p).unwrap_or_else(|e| panic!("{}", e[0]));
+        write_manifest(&p, &r.analysis).unwrap();
+
+        let j: Value = serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(j["package"], "p\"k");
+        assert_eq!(j["revision"], 3);
+        assert_eq!(j["defines"]["D"], "a\\b");
+
+        let modules = j["modules"].as_array().unwrap();
+        assert_eq!(modules.len(), 3);
+
+        for m in modules.iter() {
+            let name = m["name"].as_str().unwrap();
+            let new = m["new_name"].as_str().unwrap();
+
+            match r.renames.get(name) {
+                Some(n) => assert_eq!(new, n.trim_end()),
+                None => assert_eq!((name, &m["blackbox"]), ("bb", &Value::Bool(true))),
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::thread;
+use std::collections::{BTreeMap, BTreeSet};
+use log::{debug, info, log_enabled, warn, Level};
+
+use crate::hash::HashAlgo;
+use crate::pattern::{exact_name, is_pattern};
+use crate::template::{MODULE_TEMPLATE_DEFAULT, TOP_TEMPLATE_DEFAULT};
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Parameter {
+    pub file_list: Vec<String>,
+    pub defines: BTreeMap<String, Option<String>>,
+    pub inc_list: Vec<String>,
+    pub bb_set: BTreeSet<String>,
+    pub top_set: BTreeSet<String>,
+    // glob or `re:` regex for -t/-b, resolved against declared & instantiated names
+    pub top_patterns: Vec<String>,
+    pub bb_patterns: Vec<String>,
+    // add never instantiated module to top set, optionally check count of tops
+    pub auto_top: bool,
+    pub expect_tops: Option<usize>,
+    pub rev: usize,
+    pub pkg: String,
+    pub out_dir: Option<String>,
+    pub manifest: Option<String>,
+    // stub of blackbox & unmapped modules, ports copied from header or inferred
+    pub stub: Option<String>,
+    pub stub_header: Option<String>,
+    pub rename_file: bool,
+    // report conditional directives & declarations changed by defines
+    pub define_report: bool,
+    // omit module not reachable from top set
+    pub prune: bool,
+    pub force: bool,
+    pub strip_comments: bool,
+    pub keep_lines: bool,
+    pub keep_header: bool,
+    pub minify: bool,
+    // output mode, expanded text without `define, or original text with only identifiers renamed
+    pub preprocessed: bool,
+    pub preserve_directives: bool,
+    // verify subcommand, --manifest become input
+    pub verify: bool,
+    // print settings merged from config & command line, then exit
+    pub dump_config: bool,
+    pub against: Option<String>,
+    pub hash: HashAlgo,
+    // hex characters of checksum suffix, default by hash algorithm
+    pub hash_width: Option<usize>,
+    // digest of concatenated token text as before, instead of canonical token stream
+    pub legacy_hash: bool,
+    // digest not salted by pkg & rev, identical module of different releases get same name
+    pub content_addressed: bool,
+    // parser threads, default by available cpu
+    pub jobs: Option<usize>,
+    // 1st pass result & output of unchanged files, none to disable
+    pub cache_dir: Option<String>,
+    // new name format, see template.rs for placeholders
+    pub top_template: String,
+    pub module_template: String,
+}
+
+pub const PKG_DEFAULT: &str = "default";
+pub const REV_DEFAULT: usize = 0;
+
+impl Default for Parameter {
+    fn default() -> Parameter {
+        Parameter {
+            file_list: Vec::new(),
+            defines: BTreeMap::new(),
+            inc_list: Vec::new(),
+            bb_set: BTreeSet::new(),
+            top_set: BTreeSet::new(),
+            top_patterns: Vec::new(),
+            bb_patterns: Vec::new(),
+            auto_top: false,
+            expect_tops: None,
+            rev: REV_DEFAULT,
+            pkg: PKG_DEFAULT.into(),
+            out_dir: None,
+            manifest: None,
+            stub: None,
+            stub_header: None,
+            rename_file: false,
+            define_report: false,
+            prune: false,
+            force: false,
+            strip_comments: false,
+            keep_lines: false,
+            keep_header: false,
+            minify: false,
+            preprocessed: false,
+            preserve_directives: false,
+            verify: false,
+            dump_config: false,
+            against: None,
+            hash: HashAlgo::Crc32,
+            hash_width: None,
+            legacy_hash: false,
+            content_addressed: false,
+            jobs: None,
+            cache_dir: None,
+            top_template: TOP_TEMPLATE_DEFAULT.into(),
+            module_template: MODULE_TEMPLATE_DEFAULT.into(),
+        }
+    }
+}
+
+// builder for library user, e.g. Parameter::new().pkg("soc").rev(3).top("top").file("top.sv")
+impl Parameter {
+    pub fn new() -> Parameter { Parameter::default() }
+
+    pub fn file<S: Into<String>>(mut self, f: S) -> Parameter { self.file_list.push(f.into()); self }
+
+    pub fn define<S: Into<String>>(mut self, k: S, v: Option<S>) -> Parameter {
+        self.defines.insert(k.into(), v.map(|x| x.into()));
+        self
+    }
+
+    pub fn incdir<S: Into<String>>(mut self, d: S) -> Parameter { self.inc_list.push(d.into()); self }
+
+    pub fn top<S: Into<String>>(mut self, m: S) -> Parameter {
+        let m = m.into();
+        if is_pattern(&m) { self.top_patterns.push(m); } else { self.top_set.insert(exact_name(m)); }
+        self
+    }
+
+    pub fn auto_top(mut self, expect: Option<usize>) -> Parameter {
+        self.auto_top = true;
+        self.expect_tops = expect;
+        self
+    }
+
+    pub fn blackbox<S: Into<String>>(mut self, m: S) -> Parameter {
+        let m = m.into();
+        if is_pattern(&m) { self.bb_patterns.push(m); } else { self.bb_set.insert(exact_name(m)); }
+        self
+    }
+
+    pub fn pkg<S: Into<String>>(mut self, pkg: S) -> Parameter { self.pkg = pkg.into(); self }
+
+    pub fn rev(mut self, rev: usize) -> Parameter { self.rev = rev; self }
+
+    pub fn strip_comments(mut self, keep_header: bool) -> Parameter {
+        self.strip_comments = true;
+        self.keep_header = keep_header;
+        self
+    }
+
+    pub fn prune(mut self) -> Parameter { self.prune = true; self }
+
+    pub fn minify(mut self) -> Parameter { self.minify = true; self }
+
+    pub fn preprocessed(mut self) -> Parameter { self.preprocessed = true; self }
+
+    pub fn preserve_directives(mut self) -> Parameter { self.preserve_directives = true; self }
+
+    pub fn hash(mut self, algo: HashAlgo, width: Option<usize>) -> Parameter {
+        self.hash = algo;
+        self.hash_width = width;
+        self
+    }
+
+    pub fn legacy_hash(mut self) -> Parameter { self.legacy_hash = true; self }
+
+    pub fn content_addressed(mut self) -> Parameter { self.content_addressed = true; self }
+
+    pub fn templates<S: Into<String>>(mut self, top: S, module: S) -> Parameter {
+        self.top_template = top.into();
+        self.module_template = module.into();
+        self
+    }
+
+    pub fn jobs(mut self, n: usize) -> Parameter { self.jobs = Some(n); self }
+
+    pub fn cache<S: Into<String>>(mut self, dir: S) -> Parameter { self.cache_dir = Some(dir.into()); self }
+
+    pub fn job_count(&self) -> usize {
+        self.jobs.unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+
+    pub fn suffix_width(&self) -> usize {
+        self.hash_width.unwrap_or_else(|| self.hash.default_width())
+    }
+}
+
+pub fn show_info(p: &Parameter) {
+    info!("package {}, rev {}", p.pkg, p.rev);
+    info!("hash {}, suffix width {}", p.hash.name(), p.suffix_width());
+    if p.legacy_hash { info!("legacy hash, module named by its own tokens as old release did, children not folded") }
+    if p.content_addressed { info!("content addressed, module digest not salted by package & revision") }
+    info!("name template top '{}', module '{}'", p.top_template, p.module_template);
+    if p.pkg == PKG_DEFAULT { warn!("package not set, use default '{}'", p.pkg) }
+    if p.rev == REV_DEFAULT { warn!("revision not set, use default {}", p.rev) }
+    if p.top_set.is_empty() && p.top_patterns.is_empty() && !p.auto_top { warn!("top set is empty, use -t or --auto-top") }
+
+    if log_enabled!(Level::Debug) {
+        debug!("define list:");
+        for (k, v) in p.defines.iter() {
+            match v {
+                None => debug!("  - {}", k),
+                Some(v1) => debug!("  - {}={}", k, v1)
+            }
+        }
+
+        debug!("file list:");
+        for f in p.file_list.iter() {
+            debug!("  - {}", f);
+        }
+
+        debug!("include path list:");
+        for i in p.inc_list.iter() {
+            debug!("  - {}", i);
+        }
+
+        debug!("blackbox list:");
+        for i in p.bb_set.iter().chain(p.bb_patterns.iter()) {
+            debug!("  - {}", i);
+        }
+
+        if let Some(d) = &p.out_dir {
+            debug!("output directory: {}", d);
+        }
+
+        if let Some(m) = &p.manifest {
+            debug!("manifest: {}", m);
+        }
+
+        if let Some(s) = &p.stub {
+            debug!("stub: {}", s);
+        }
+
+        if let Some(c) = &p.cache_dir {
+            debug!("cache: {}", c);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeSet;
+use log::{info, warn};
+use regex::Regex;
+
+use crate::error::Error;
+use crate::param::Parameter;
+use crate::rewrite::{check_collision, Analysis};
+
+// `re:<regex>` or glob with * ? [...], otherwise an exact name (escaped identifier is always exact)
+pub(crate) fn is_pattern(s: &str) -> bool {
+    !s.starts_with('\\') && (s.starts_with("re:") || s.contains(['*', '?', '[']))
+}
+
+// escaped identifier may be given with its terminating space, names in analysis have none
+pub(crate) fn exact_name(s: String) -> String {
+    if s.starts_with('\\') { s.trim_end().to_string() } else { s }
+}
+
+fn glob_to_regex(s: &str) -> String {
+    let mut res = String::from("^");
+    let mut in_class = false;
+
+    for c in s.chars() {
+        match c {
+            '*' if !in_class => res.push_str(".*"),
+            '?' if !in_class => res.push('.'),
+            '[' if !in_class => { in_class = true; res.push('['); }
+            ']' if in_class  => { in_class = false; res.push(']'); }
+            '!' if in_class && res.ends_with('[') => res.push('^'),
+            _ if in_class    => { if c == '\\' { res.push('\\'); } res.push(c); }
+            _                => res.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    res.push('$');
+    res
+}
+
+pub(crate) fn compile(s: &str) -> Result<Regex, String> {
+    let re = match s.strip_prefix("re:") {
+        Some(r) => r.to_string(),
+        None    => glob_to_regex(s),
+    };
+
+    Regex::new(&re).map_err(|e| format!("invalid pattern '{}': {}", s, e))
+}
+
+fn expand(kind: &str, patterns: &[String], names: &BTreeSet<&String>, set: &mut BTreeSet<String>) -> Result<(), Error> {
+    for pat in patterns.iter() {
+        let re = compile(pat).map_err(Error::Arg)?;
+        let found: Vec<&String> = names.iter().filter(|m| re.is_match(m)).cloned().collect();
+
+        if found.is_empty() {
+            warn!("  {} pattern {} match nothing", kind, pat);
+            continue;
+        }
+
+        info!("  {} pattern {}:", kind, pat);
+        for m in found.into_iter() {
+            info!("    {}", m);
+            set.insert(m.clone());
+        }
+    }
+
+    Ok(())
+}
+
+// add modules matched by -t/-b patterns to top & blackbox set
+pub fn resolve_patterns(p: &mut Parameter, a: &Analysis) -> Result<(), Error> {
+    if p.top_patterns.is_empty() && p.bb_patterns.is_empty() { return Ok(()); }
+
+    info!("resolve patterns:");
+
+    // top must be declared, blackbox is usually only instantiated
+    let declared: BTreeSet<&String> = a.module_map.keys().collect();
+    let mut known = declared.clone();
+    known.extend(a.module_ref.iter());
+
+    expand("top", &p.top_patterns, &declared, &mut p.top_set)?;
+    expand("blackbox", &p.bb_patterns, &known, &mut p.bb_set)?;
+
+    check_collision(p, &a.module_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::{Mutex, Once};
+    use log::{Level, Log, Metadata, Record};
+
+    use super::{compile, exact_name, is_pattern, resolve_patterns};
+    use crate::error::Error;
+    use crate::hash::Checksum;
+    use crate::param::Parameter;
+    use crate::rewrite::Analysis;
+
+    // keep warnings for check, other tests of the crate may log here as well
+    struct Capture(Mutex<Vec<String>>);
+
+    impl Log for Capture {
+        fn enabled(&self, m: &Metadata) -> bool { m.level() <= Level::Warn }
+        fn log(&self, r: &Record) { if self.enabled(r.metadata()) { self.0.lock().unwrap().push(r.args().to_string()); } }
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+    static INIT: Once = Once::new();
+
+    fn analysis(declared: &[&str], referenced: &[&str]) -> Analysis {
+        Analysis {
+            module_map: declared.iter().enumerate().map(|(i, m)| (m.to_string(), Checksum::Crc32(i as u32))).collect(),
+            module_ref: referenced.iter().map(|m| m.to_string()).collect(),
+            root_ref: BTreeSet::new(),
+            child_map: BTreeMap::new(),
+            decl_map: BTreeMap::new(),
+            rename_map: BTreeMap::new(),
+            port_use: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn glob() {
+        let re = compile("cpu_*").unwrap();
+        assert!(re.is_match("cpu_") && re.is_match("cpu_core") && !re.is_match("my_cpu_core"));
+
+        let re = compile("ram?x").unwrap();
+        assert!(re.is_match("ram1x") && !re.is_match("ramx") && !re.is_match("ram12x"));
+
+        let re = compile("fifo_[!ab]*").unwrap();
+        assert!(re.is_match("fifo_c") && !re.is_match("fifo_a1") && !re.is_match("fifo_b"));
+
+        let re = compile("fifo_[ab]").unwrap();
+        assert!(re.is_match("fifo_a") && !re.is_match("fifo_c"));
+
+        // other regex character is literal in glob
+        let re = compile("a.b$c").unwrap();
+        assert!(re.is_match("a.b$c") && !re.is_match("axb$c"));
+    }
+
+    #[test]
+    fn regex() {
+        let re = compile("re:^(tb|sim)_").unwrap();
+        assert!(re.is_match("tb_top") && re.is_match("sim_x") && !re.is_match("top_tb_"));
+
+        let e = compile("re:a(b").unwrap_err();
+        assert!(e.starts_with("invalid pattern 're:a(b'"), "{}", e);
+    }
+
+    #[test]
+    fn pattern_or_name() {
+        for s in ["a*", "a?", "a[0]", "re:a"] { assert!(is_pattern(s), "{}", s); }
+        for s in ["a", "a$b", "\\mem[0] ", "\\a*b"] { assert!(!is_pattern(s), "{}", s); }
+
+        assert_eq!(exact_name("\\mem[0] ".to_string()), "\\mem[0]");
+        assert_eq!(exact_name("top".to_string()), "top");
+
+        let p = Parameter::new().top("\\mem[0] ").blackbox("\\x*y");
+        assert!(p.top_patterns.is_empty() && p.bb_patterns.is_empty());
+        assert!(p.top_set.contains("\\mem[0]") && p.bb_set.contains("\\x*y"));
+    }
+
+    #[test]
+    fn resolve() {
+        INIT.call_once(|| { log::set_logger(&CAPTURE).unwrap(); log::set_max_level(log::LevelFilter::Warn); });
+
+        let a = analysis(&["cpu_a", "cpu_b", "top"], &["cpu_a", "cpu_b", "hard_ip"]);
+        let mut p = Parameter::new().top("t*").blackbox("hard_*").blackbox("nothing_*");
+
+        resolve_patterns(&mut p, &a).unwrap();
+        assert_eq!(p.top_set.iter().collect::<Vec<_>>(), ["top"]);
+        assert_eq!(p.bb_set.iter().collect::<Vec<_>>(), ["hard_ip"]);
+
+        // top pattern only match declared module
+        let mut p = Parameter::new().top("hard_*");
+        resolve_patterns(&mut p, &a).unwrap();
+        assert!(p.top_set.is_empty());
+
+        let log = CAPTURE.0.lock().unwrap();
+        assert!(log.iter().any(|l| l == "  blackbox pattern nothing_* match nothing"), "{:?}", log);
+        assert!(log.iter().any(|l| l == "  top pattern hard_* match nothing"), "{:?}", log);
+
+        let mut p = Parameter::new().top("re:(");
+        assert!(matches!(resolve_patterns(&mut p, &a), Err(Error::Arg(_))));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap};
+use log::{info, warn};
+use sv_parser::{parse_sv, Defines};
+
+use crate::param::Parameter;
+use crate::rewrite::{declaration, node_end, text_of, Analysis};
+
+// line, directive, macro name
+type Cond = (usize, &'static str, String);
+
+// `ifdef/`ifndef/`elsif and `define in source text, comment & string skipped
+pub(crate) fn scan_directives(text: &str) -> (Vec<Cond>, BTreeSet<String>) {
+    let mut conds: Vec<Cond> = Vec::new();
+    let mut defined: BTreeSet<String> = BTreeSet::new();
+
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    let mut line = 1;
+
+    let ident = |start: usize| -> (usize, &str) {
+        let mut j = start;
+        while j < bytes.len() && (bytes[j].is_ascii_alphanumeric() || bytes[j] == b'_' || bytes[j] == b'$') { j += 1; }
+        (j, &text[start..j])
+    };
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => { line += 1; i += 1; }
+            b'/' if bytes.get(i+1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' { i += 1; }
+            }
+            b'/' if bytes.get(i+1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i+1) == Some(&b'/')) {
+                    if bytes[i] == b'\n' { line += 1; }
+                    i += 1;
+                }
+                i += 2;
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                    if bytes[i] == b'\\' { i += 1; }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'`' => {
+                let (j, word) = ident(i + 1);
+                i = j;
+
+                let kind = match word {
+                    "ifdef"  => "ifdef",
+                    "ifndef" => "ifndef",
+                    "elsif"  => "elsif",
+                    "define" => "define",
+                    _        => continue,
+                };
+
+                while i < bytes.len() && (bytes[i] == b' ' || bytes[i] == b'\t') { i += 1; }
+                let (j, name) = ident(i);
+                i = j;
+
+                if name.is_empty() { continue; }
+
+                if kind == "define" { defined.insert(name.to_string()); }
+                else { conds.push((line, kind, name.to_string())); }
+            }
+            _ => { i += 1; }
+        }
+    }
+
+    (conds, defined)
+}
+
+// top level declarations when parsed without any +define+
+fn declared_without_defines(p: &Parameter) -> BTreeSet<String> {
+    let mut res: BTreeSet<String> = BTreeSet::new();
+    let defines: Defines = HashMap::new();
+
+    for file in p.file_list.iter() {
+        let syntax_tree = match parse_sv(file, &defines, &p.inc_list, false, false) {
+            Ok((x, _)) => x,
+            Err(_) => {
+                warn!("  {} can not be parsed without defines, skipped", file);
+                continue;
+            }
+        };
+
+        let mut curr_end: usize = 0;
+
+        for node in &syntax_tree {
+            if let Some((_, loc)) = declaration(&node) {
+                if loc.offset >= curr_end {
+                    match text_of(&syntax_tree, file, &loc) {
+                        Ok(x)  => { res.insert(x.to_string()); }
+                        Err(e) => warn!("  {}, skipped", e),
+                    }
+                    curr_end = node_end(node.clone());
+                }
+            }
+        }
+    }
+
+    res
+}
+
+// audit of configuration dependent content: conditional directives & declarations changed by +define+
+pub fn define_report(p: &Parameter, a: &Analysis) {
+    if !p.define_report { return; }
+
+    info!("define report:");
+
+    let mut file_conds: Vec<(&String, Vec<Cond>)> = Vec::new();
+    let mut defined_in_src: BTreeSet<String> = BTreeSet::new();
+
+    for file in p.file_list.iter() {
+        let text = match fs::read_to_string(PathBuf::from(file)) {
+            Ok(x) => x,
+            Err(e) => { warn!("  {}: {}", file, e); continue; }
+        };
+
+        let (conds, defined) = scan_directives(&text);
+        defined_in_src.extend(defined);
+        file_conds.push((file, conds));
+    }
+
+    let mut tested: BTreeSet<String> = BTreeSet::new();
+
+    for (file, conds) in file_conds.into_iter() {
+        if conds.is_empty() { continue; }
+
+        info!("  {}", file);
+
+        for (line, kind, name) in conds.into_iter() {
+            let state = if p.defines.contains_key(&name) { "defined" }
+                        else if defined_in_src.contains(&name) { "defined in source" }
+                        else { "undefined" };
+            info!("    {}: `{} {} ({})", line, kind, name, state);
+            tested.insert(name);
+        }
+    }
+
+    // likely typo in +define+
+    for k in p.defines.keys() {
+        if !tested.contains(k) { warn!("  +define+{} is never tested by `ifdef, `ifndef or `elsif", k); }
+    }
+
+    let without = declared_without_defines(p);
+    let mut same = true;
+
+    for m in a.decl_map.keys().filter(|m| !without.contains(*m)) {
+        info!("  {} {} only declared with defines", a.decl_map[m].kind, m);
+        same = false;
+    }
+
+    for m in without.iter().filter(|m| !a.decl_map.contains_key(*m)) {
+        warn!("  {} only declared without defines, hidden by defines", m);
+        same = false;
+    }
+
+    if same { info!("  declarations not affected by defines"); }
+}
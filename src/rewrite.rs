@@ -0,0 +1,1171 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use log::{debug, info, log_enabled, warn, Level};
+use sv_parser::{unwrap_node, CompilerDirective, Locate, RefNode, SyntaxTree, WhiteSpace};
+
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::hash::{Checksum, Hasher};
+use crate::param::Parameter;
+use crate::report::scan_directives;
+use crate::stub::{self, PortUse};
+use crate::template;
+use crate::parse_list;
+
+type Loc = (usize, usize, u32);
+type FileLoc = (String, usize, usize, u32);
+
+pub struct Output {
+    pub text: String,
+    // new name of the first module declared in file
+    pub first_module: Option<String>,
+}
+
+// build output text of one file according to comment & whitespace mode
+struct Emitter {
+    text: String,
+    line: usize,
+    // output lines where comment be removed
+    stripped: BTreeSet<usize>,
+    // minify only, separator before next token
+    space: bool,
+    newline: bool,
+    // code token emitted since last newline
+    code_on_line: bool,
+}
+
+impl Emitter {
+    fn new() -> Emitter {
+        Emitter {
+            text: String::new(), line: 0, stripped: BTreeSet::new(), space: false, newline: false, code_on_line: false,
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.line += s.matches('\n').count();
+        if s.contains('\n') { self.code_on_line = false; }
+    }
+
+    // whitespace & comments before a pruned declaration on its first line, false when code precede it
+    fn drop_line(&mut self) -> bool {
+        if self.code_on_line { return false; }
+        self.text.truncate(self.text.rfind('\n').map(|i| i + 1).unwrap_or(0));
+        true
+    }
+
+    fn whitespace(&mut self, p: &Parameter, s: &str) {
+        if p.minify { self.space = true; }
+        else { self.push(s); }
+    }
+
+    fn comment(&mut self, p: &Parameter, s: &str, keep: bool) {
+        if keep {
+            let code = self.code_on_line;
+            self.token(p, s, false);
+            self.code_on_line = code && !s.contains('\n');
+        }
+        else if p.minify {
+            self.space = true;
+        }
+        else {
+            self.stripped.insert(self.line);
+
+            let n = s.matches('\n').count();
+            if p.keep_lines && n > 0 { self.push(&"\n".repeat(n)); }
+            else if s.ends_with('\n') { self.push("\n"); }
+            else { self.push(" "); }
+        }
+    }
+
+    fn token(&mut self, p: &Parameter, s: &str, directive: bool) {
+        if p.minify {
+            if !self.text.is_empty() && !self.text.ends_with('\n') {
+                // compiler directive must end with newline
+                if self.newline && !directive { self.push("\n"); }
+                else if self.space { self.push(" "); }
+            }
+
+            self.space = false;
+            self.newline = directive;
+        }
+
+        self.push(s);
+        self.code_on_line = !s.ends_with('\n');
+    }
+
+    fn finish(self, p: &Parameter) -> String {
+        if self.stripped.is_empty() { return self.text; }
+
+        let mut res = String::new();
+
+        for (i, l) in self.text.split_inclusive('\n').enumerate() {
+            if !self.stripped.contains(&i) {
+                res.push_str(l);
+            }
+            // drop line only hold comment
+            else if p.keep_lines || !l.trim().is_empty() {
+                res.push_str(l.trim_end());
+                if l.ends_with('\n') { res.push('\n'); }
+            }
+        }
+
+        res
+    }
+}
+
+// result of 1st pass
+pub struct Analysis {
+    pub module_map: BTreeMap<String, Checksum>,
+    pub module_ref: BTreeSet<String>,
+    // instantiated or referenced outside any module, e.g. by config or bind to unknown scope
+    pub root_ref: BTreeSet<String>,
+    // module -> instantiated or referenced modules
+    pub child_map: BTreeMap<String, BTreeSet<String>>,
+    pub decl_map: BTreeMap<String, Decl>,
+    pub(crate) rename_map: BTreeMap<FileLoc, (String, bool)>,
+    pub(crate) port_use: BTreeMap<String, PortUse>,
+}
+
+pub struct Decl {
+    pub kind: &'static str,
+    pub file: String,
+    pub line: usize,
+}
+
+// source file & line of a token, follow `include to original file,
+// offset of newlines read once per file & kept in `lines`
+fn origin_of(syntax_tree: &SyntaxTree, path: &str, loc: &Locate,
+             lines: &mut BTreeMap<PathBuf, Option<Vec<usize>>>) -> (String, usize) {
+    match syntax_tree.get_origin(loc) {
+        Some((f, pos)) => {
+            let newlines = lines.entry(f.clone()).or_insert_with(|| {
+                fs::read(f).ok().map(|t| t.iter().enumerate().filter(|(_, c)| **c == b'\n').map(|(i, _)| i).collect())
+            });
+
+            let line = match newlines {
+                Some(v) => v.partition_point(|x| *x < pos) + 1,
+                None    => loc.line as usize,
+            };
+            (f.to_string_lossy().to_string(), line)
+        }
+        None => (path.to_string(), loc.line as usize),
+    }
+}
+
+// text of token, error at its place rather than panic when parser give none
+pub(crate) fn text_of<'a>(syntax_tree: &'a SyntaxTree, path: &str, loc: &Locate) -> Result<&'a str, Error> {
+    syntax_tree.get_str(loc).ok_or_else(|| {
+        let (file, line) = origin_of(syntax_tree, path, loc, &mut BTreeMap::new());
+        Error::Rewrite(format!("{}:{}: no text of token", file, line))
+    })
+}
+
+// kind and identifier of module, interface, program, package, udp or class declaration
+pub(crate) fn declaration(node: &RefNode) -> Option<(&'static str, Locate)> {
+    let (kind, id) = match node {
+        RefNode::ModuleDeclaration(x) if unwrap_node!(*x, ModuleDeclarationAnsi, ModuleDeclarationNonansi).is_some() =>
+            ("module", unwrap_node!(*x, ModuleIdentifier)),
+        RefNode::InterfaceDeclaration(x) if unwrap_node!(*x, InterfaceDeclarationAnsi, InterfaceDeclarationNonansi,
+                                                         InterfaceDeclarationWildcard).is_some() =>
+            ("interface", unwrap_node!(*x, InterfaceIdentifier)),
+        RefNode::ProgramDeclaration(x) if unwrap_node!(*x, ProgramDeclarationAnsi, ProgramDeclarationNonansi,
+                                                       ProgramDeclarationWildcard).is_some() =>
+            ("program", unwrap_node!(*x, ProgramIdentifier)),
+        RefNode::UdpDeclaration(x) if unwrap_node!(*x, UdpDeclarationAnsi, UdpDeclarationNonansi,
+                                                   UdpDeclarationWildcard).is_some() =>
+            ("udp", unwrap_node!(*x, UdpIdentifier)),
+        RefNode::PackageDeclaration(x) => ("package", unwrap_node!(*x, PackageIdentifier)),
+        RefNode::ClassDeclaration(x)   => ("class", unwrap_node!(*x, ClassIdentifier)),
+        _ => return None,
+    };
+
+    Some((kind, get_identifier(id?)?))
+}
+
+// definition & instance identifier of module, interface, program or udp instantiation
+fn instantiation(node: &RefNode) -> Option<(Locate, Option<Locate>)> {
+    let (id, inst) = match node {
+        RefNode::ModuleInstantiation(x)    => (unwrap_node!(*x, ModuleIdentifier), unwrap_node!(*x, InstanceIdentifier)),
+        RefNode::InterfaceInstantiation(x) => (unwrap_node!(*x, InterfaceIdentifier), unwrap_node!(*x, InstanceIdentifier)),
+        RefNode::ProgramInstantiation(x)   => (unwrap_node!(*x, ProgramIdentifier), unwrap_node!(*x, InstanceIdentifier)),
+        RefNode::UdpInstantiation(x)       => (unwrap_node!(*x, UdpIdentifier), unwrap_node!(*x, InstanceIdentifier)),
+        _ => return None,
+    };
+
+    Some((get_identifier(id?)?, inst.and_then(get_identifier)))
+}
+
+// identifier may refer to a declared design element, e.g. import, scope, port type, label
+fn reference(node: &RefNode) -> Option<Locate> {
+    match node {
+        RefNode::ModuleIdentifier(_) | RefNode::InterfaceIdentifier(_) | RefNode::ProgramIdentifier(_) |
+        RefNode::UdpIdentifier(_) | RefNode::PackageIdentifier(_) | RefNode::ClassIdentifier(_) |
+        RefNode::TypeIdentifier(_) | RefNode::NetTypeIdentifier(_) | RefNode::CellIdentifier(_) => get_identifier(node.clone()),
+        _ => None,
+    }
+}
+
+// name of a top as root instance or design, renamed but not taken as instantiated,
+// e.g. `bind top.u0 chk u1 ();`, `design work.top;`, `instance top.u0 use ...;`
+fn path_root(node: &RefNode) -> Vec<Locate> {
+    match node {
+        RefNode::BindTargetInstance(x) => {
+            // single name is instance in current scope
+            match x.nodes.0.nodes.1.first() {
+                Some((id, _, _)) if x.nodes.0.nodes.0.is_none() => get_identifier(id.into()).into_iter().collect(),
+                _ => Vec::new(),
+            }
+        }
+        RefNode::DesignStatement(x) => x.nodes.1.iter().filter_map(|(_, c)| get_identifier(c.into())).collect(),
+        RefNode::InstName(x) => get_identifier((&x.nodes.0).into()).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// module hold instance or reference at offset, none for file level one, e.g. bind outside module
+fn owner(offset: usize, curr_module: &Option<String>, curr_end: usize,
+         bind: &Option<(Option<String>, usize)>) -> Option<String> {
+    match bind {
+        Some((target, end)) if offset < *end => target.clone(),
+        _ if offset < curr_end               => curr_module.clone(),
+        _                                    => None,
+    }
+}
+
+pub(crate) fn node_end(node: RefNode) -> usize {
+    node.into_iter()
+        .filter_map(|n| if let RefNode::Locate(x) = n { Some(x.offset + x.len) } else { None })
+        .max()
+        .unwrap_or(0)
+}
+
+// last token in node, whitespace & comment after it excluded
+pub(crate) fn last_token(node: RefNode) -> Option<Locate> {
+    let mut ws: BTreeSet<Loc> = BTreeSet::new();
+    let mut res: Option<Locate> = None;
+
+    for n in node.into_iter() {
+        match n {
+            RefNode::WhiteSpace(x) => {
+                for l in x.into_iter() {
+                    if let RefNode::Locate(loc) = l { ws.insert((loc.offset, loc.len, loc.line)); }
+                }
+            }
+            RefNode::Locate(x) if !ws.contains(&(x.offset, x.len, x.line))
+                                  && res.map(|r| x.offset > r.offset).unwrap_or(true) => res = Some(*x),
+            _ => (),
+        }
+    }
+
+    res
+}
+
+fn code_end(node: RefNode) -> usize {
+    last_token(node).map(|x| x.offset + x.len).unwrap_or(0)
+}
+
+// declared module not reachable from top set, through instantiation or reference
+pub fn unreachable(p: &Parameter, a: &Analysis) -> BTreeSet<String> {
+    let mut live: BTreeSet<String> = BTreeSet::new();
+    // file level reference stay valid only with its target kept
+    let mut stack: Vec<&String> = p.top_set.iter().chain(a.root_ref.iter()).collect();
+
+    while let Some(m) = stack.pop() {
+        if !live.insert(m.clone()) { continue; }
+
+        if let Some(children) = a.child_map.get(m) {
+            stack.extend(children.iter().filter(|x| !live.contains(*x)));
+        }
+    }
+
+    a.module_map.keys().filter(|m| !live.contains(*m)).cloned().collect()
+}
+
+pub(crate) fn new_name(p: &Parameter, module_map: &BTreeMap<String, Checksum>, name: &str) -> Option<String> {
+    match (p.top_set.contains(name), module_map.get(name)) {
+        (true,  c)           => template::render(p, true, name, c).ok(),
+        (false, Some(cksum)) => template::render(p, false, name, Some(cksum)).ok(),
+        _                    => None,
+    }
+}
+
+// fold final digest of every instantiated module into its parent, children first
+fn resolve_digest(p: &Parameter,
+                  own_map: &BTreeMap<String, Checksum>,
+                  child_map: &BTreeMap<String, BTreeSet<String>>) -> Result<BTreeMap<String, Checksum>, Vec<String>> {
+    fn visit(m: &str,
+             p: &Parameter,
+             own_map: &BTreeMap<String, Checksum>,
+             child_map: &BTreeMap<String, BTreeSet<String>>,
+             stack: &mut Vec<String>,
+             res: &mut BTreeMap<String, Checksum>) -> Result<Checksum, Vec<String>> {
+        if let Some(cksum) = res.get(m) { return Ok(cksum.clone()); }
+
+        if let Some(idx) = stack.iter().position(|x| x == m) {
+            let mut cycle = stack[idx..].to_vec();
+            cycle.push(m.to_string());
+            return Err(cycle);
+        }
+
+        // leaf keep its own digest, its name not changed by folding
+        let children = match child_map.get(m) {
+            Some(x) if !x.is_empty() => x,
+            _ => {
+                res.insert(m.to_string(), own_map[m].clone());
+                return Ok(own_map[m].clone());
+            }
+        };
+
+        stack.push(m.to_string());
+
+        let mut digest = Hasher::new(p.hash);
+        digest.update_checksum(&own_map[m]);
+
+        for c in children.iter() {
+            digest.update(c.as_bytes());
+
+            // blackbox or unmapped module only contribute its name
+            if own_map.contains_key(c) {
+                let cksum = visit(c, p, own_map, child_map, stack, res)?;
+                digest.update_checksum(&cksum);
+            }
+        }
+
+        stack.pop();
+
+        let cksum = digest.finalize();
+        res.insert(m.to_string(), cksum.clone());
+
+        Ok(cksum)
+    }
+
+    let mut res: BTreeMap<String, Checksum> = BTreeMap::new();
+    let mut stack: Vec<String> = Vec::new();
+
+    for m in own_map.keys() {
+        visit(m, p, own_map, child_map, &mut stack, &mut res)?;
+    }
+
+    // old release named every module by its own digest, folding only to report cycle
+    if p.legacy_hash { return Ok(own_map.clone()); }
+
+    Ok(res)
+}
+
+// every new name should be legal and unique, and distinct bodies keep distinct checksum suffixes,
+// truncated checksum or template may break it
+pub(crate) fn check_collision(p: &Parameter, module_map: &BTreeMap<String, Checksum>) -> Result<(), Error> {
+    let mut name_map: BTreeMap<String, &String> = BTreeMap::new();
+
+    for (m, cksum) in module_map.iter() {
+        let n = template::render(p, p.top_set.contains(m), m, Some(cksum)).map_err(Error::Rewrite)?;
+
+        if let Some(other) = name_map.get(&n) {
+            return Err(Error::Rewrite(format!("{} and {} both renamed to {}, \
+                                               use wider --hash-width, other --hash or template", other, m, n.trim_end())));
+        }
+
+        name_map.insert(n, m);
+    }
+
+    // distinct bodies should keep distinct suffixes, whatever the name
+    let width = template::module_hash_width(p);
+    let mut suffix_map: BTreeMap<String, (&String, &Checksum)> = BTreeMap::new();
+
+    for (m, cksum) in module_map.iter().filter(|(m, _)| !p.top_set.contains(*m)) {
+        let s = cksum.suffix(width);
+
+        match suffix_map.get(&s) {
+            Some((other, c)) if *c != cksum => {
+                return Err(Error::Rewrite(format!("{} and {} have different content but same checksum suffix {}, \
+                                                   use wider --hash-width or other --hash", other, m, s)));
+            }
+            _ => { suffix_map.insert(s, (m, cksum)); },
+        }
+    }
+
+    Ok(())
+}
+
+// declaration & instantiation in source order, digest at end of declaration
+pub(crate) enum Fact {
+    Decl { name: String, kind: &'static str, file: String, line: usize },
+    Inst { name: String, owner: Option<String> },
+    Digest { name: String, digest: Checksum },
+}
+
+// 1st pass result of one file, not depend on other files so can be cached
+#[derive(Default)]
+pub(crate) struct FileFacts {
+    pub(crate) facts: Vec<Fact>,
+    // identifier of declaration (true) & instantiation
+    pub(crate) rename: Vec<(Loc, String, bool)>,
+    // reference only valid when name be declared, with owner, and whether only renamed
+    pub(crate) soft_ref: Vec<(Loc, String, Option<String>, bool)>,
+    pub(crate) port_use: BTreeMap<String, PortUse>,
+    // files tokens come from, e.g. by `include
+    pub(crate) deps: BTreeSet<String>,
+}
+
+// same value in any case & digit grouping, e.g. 8'hFF_FF and 8'hffff
+fn canonical_number(s: &str) -> String {
+    s.chars().filter(|c| *c != '_').map(|c| c.to_ascii_lowercase()).collect()
+}
+
+// digest uniquified by pkg & rev, unless same content should share name across releases
+fn salted(p: &Parameter) -> Hasher {
+    let mut digest = Hasher::new(p.hash);
+
+    if !p.content_addressed {
+        digest.update(p.pkg.as_bytes());
+        digest.update(p.rev.to_string().as_bytes());
+    }
+
+    digest
+}
+
+fn scan(p: &Parameter, path: &str, syntax_tree: &SyntaxTree) -> Result<FileFacts, Error> {
+    let mut res = FileFacts::default();
+    let mut renamed: BTreeSet<Loc> = BTreeSet::new();
+    let mut soft_ref: Vec<(Loc, String, Option<String>)> = Vec::new();
+    let mut name_only: BTreeSet<Loc> = BTreeSet::new();
+    let mut deps: BTreeSet<&PathBuf> = BTreeSet::new();
+
+    let mut lines: BTreeMap<PathBuf, Option<Vec<usize>>> = BTreeMap::new();
+
+    let mut whitespace_or_comment: BTreeSet<Loc> = BTreeSet::new();
+    let mut curr_module: Option<String> = None;
+    let mut curr_kind: &str = "";
+    let mut curr_end: usize = 0;
+    let mut curr_digest = Hasher::new(p.hash);
+    // old digest of module under --legacy-hash, run from its declaration to next module one, nested one included
+    let mut legacy: Option<(Option<String>, Hasher)> = None;
+    // target module & end of bind directive
+    let mut bind: Option<(Option<String>, usize)> = None;
+    // end of number literal, its tokens be normalized
+    let mut number_end: usize = 0;
+
+    for node in syntax_tree {
+        if p.legacy_hash {
+            if let Some(("module", loc)) = declaration(&node) {
+                let name = if loc.offset >= curr_end { Some(text_of(syntax_tree, path, &loc)?.to_string()) } else { None };
+
+                if let Some((Some(m), digest)) = legacy.replace((name, salted(p))) {
+                    res.facts.push(Fact::Digest { name: m, digest: digest.finalize() });
+                }
+            }
+        }
+
+        if let Some((kind, loc)) = declaration(&node) {
+            // nested declaration, e.g. class in package, is part of outer one
+            if loc.offset >= curr_end {
+                let name = text_of(syntax_tree, path, &loc)?;
+
+                renamed.insert((loc.offset, loc.len, loc.line));
+                res.rename.push(((loc.offset, loc.len, loc.line), name.to_string(), true));
+
+                if let Some(m) = curr_module.filter(|_| !(p.legacy_hash && curr_kind == "module")) {
+                    let digest = std::mem::replace(&mut curr_digest, Hasher::new(p.hash)).finalize();
+                    res.facts.push(Fact::Digest { name: m, digest });
+                }
+
+                debug!("    {} {}", kind, name);
+
+                let (file, line) = origin_of(syntax_tree, path, &loc, &mut lines);
+                res.facts.push(Fact::Decl { name: name.to_string(), kind, file, line });
+
+                curr_module = Some(name.to_string());
+                curr_kind = kind;
+                curr_end = node_end(node.clone());
+                curr_digest = salted(p);
+            }
+        }
+        else if let Some((mid_loc, iid_loc)) = instantiation(&node) {
+            let mod_name = text_of(syntax_tree, path, &mid_loc)?;
+            let inst_name = iid_loc.and_then(|x| syntax_tree.get_str(&x)).unwrap_or("");
+
+            renamed.insert((mid_loc.offset, mid_loc.len, mid_loc.line));
+            res.rename.push(((mid_loc.offset, mid_loc.len, mid_loc.line), mod_name.to_string(), false));
+
+            debug!("      - {}: {}", inst_name, mod_name);
+
+            stub::record(&mut res.port_use, mod_name, &node, syntax_tree);
+
+            let owner = owner(mid_loc.offset, &curr_module, curr_end, &bind);
+            res.facts.push(Fact::Inst { name: mod_name.to_string(), owner });
+        }
+        else if let Some(loc) = reference(&node) {
+            let key = (loc.offset, loc.len, loc.line);
+
+            if !renamed.contains(&key) && !name_only.contains(&key) {
+                let name = text_of(syntax_tree, path, &loc)?;
+                soft_ref.push((key, name.to_string(), owner(loc.offset, &curr_module, curr_end, &bind)));
+            }
+        }
+
+        for loc in path_root(&node).into_iter() {
+            let key = (loc.offset, loc.len, loc.line);
+            let name = text_of(syntax_tree, path, &loc)?;
+
+            name_only.insert(key);
+            soft_ref.push((key, name.to_string(), None));
+        }
+
+        // bound instance belong to target module rather than the enclosing one
+        if let RefNode::BindDirective(x) = node {
+            let target = match unwrap_node!(x, BindTargetScope) {
+                Some(RefNode::BindTargetScope(t)) => get_identifier(t.into()).and_then(|l| syntax_tree.get_str(&l)),
+                _ => None,
+            };
+
+            // bind to instance path, which start in enclosing module or, at file level, from a top
+            let root = unwrap_node!(x, BindTargetInstance)
+                .and_then(|t| path_root(&t).first().and_then(|l| syntax_tree.get_str(l)).map(|s| s.to_string()));
+            let target = target.map(|t| t.to_string())
+                .or_else(|| if node_end(node.clone()) <= curr_end { curr_module.clone() } else { root });
+
+            bind = Some((target, node_end(node.clone())));
+            debug!("      bind {}", bind.as_ref().and_then(|b| b.0.as_deref()).unwrap_or("?"));
+        }
+
+        match node {
+            RefNode::Locate(x) => {
+                if let Some((f, _)) = syntax_tree.get_origin(x) { deps.insert(f); }
+
+                if whitespace_or_comment.contains(&(x.offset, x.len, x.line)) {
+                    continue;
+                }
+                else if p.legacy_hash {
+                    let str = text_of(syntax_tree, path, x)?;
+                    curr_digest.update(str.as_bytes());
+                    if let Some((_, digest)) = legacy.as_mut() { digest.update(str.as_bytes()); }
+                }
+                else {
+                    // canonical token stream, separator keep token boundary
+                    let str = text_of(syntax_tree, path, x)?;
+                    if x.offset < number_end { curr_digest.update(canonical_number(str).as_bytes()); }
+                    else { curr_digest.update(str.as_bytes()); }
+                    curr_digest.update(b"\0");
+                }
+            }
+
+            RefNode::WhiteSpace(x) => {
+                if let Some(RefNode::Locate(loc)) = unwrap_node!(x, Locate) {
+                    whitespace_or_comment.insert((loc.offset, loc.len, loc.line));
+                }
+            }
+
+            RefNode::Number(_) | RefNode::UnsignedNumber(_) | RefNode::RealNumber(_) |
+            RefNode::UnbasedUnsizedLiteral(_) => number_end = number_end.max(code_end(node.clone())),
+
+            _ => (),
+        }
+    }
+
+    if let Some(m) = curr_module.filter(|_| !(p.legacy_hash && curr_kind == "module")) {
+        res.facts.push(Fact::Digest { name: m, digest: curr_digest.finalize() });
+    }
+
+    if let Some((Some(m), digest)) = legacy {
+        res.facts.push(Fact::Digest { name: m, digest: digest.finalize() });
+    }
+
+    res.soft_ref = soft_ref.into_iter().map(|(k, n, o)| (k, n, o, name_only.contains(&k))).collect();
+    res.deps = deps.into_iter().map(|f| f.to_string_lossy().to_string()).collect();
+
+    Ok(res)
+}
+
+pub fn analyze(p: &Parameter, st_map: &BTreeMap<String, SyntaxTree>, cache: &mut Cache) -> Result<Analysis, Error> {
+    template::check(p).map_err(Error::Arg)?;
+
+    let mut own_map: BTreeMap<String, Checksum> = BTreeMap::new();
+    let mut decl_map: BTreeMap<String, Decl> = BTreeMap::new();
+    let mut child_map: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut rename_map: BTreeMap<FileLoc, (String, bool)> = BTreeMap::new();
+    let mut module_ref: BTreeSet<String> = BTreeSet::new();
+    let mut root_ref: BTreeSet<String> = BTreeSet::new();
+    // reference other than instantiation, only valid when name be declared
+    let mut soft_ref: Vec<(FileLoc, String, Option<String>, bool)> = Vec::new();
+    let mut port_use: BTreeMap<String, PortUse> = BTreeMap::new();
+
+    // ------------- first pass --------------
+    info!("rewreite, 1st pass...");
+
+    let paths: BTreeSet<String> = st_map.keys().chain(cache.paths()).cloned().collect();
+
+    for path in paths.into_iter() {
+        let facts = match st_map.get(&path) {
+            Some(syntax_tree) => {
+                info!("  {} ...", path);
+                cache.store_facts(&path, scan(p, &path, syntax_tree)?)
+            }
+            None => {
+                info!("  {} (cached)", path);
+                cache.facts(&path).unwrap()
+            }
+        };
+
+        for f in facts.facts.iter() {
+            match f {
+                Fact::Decl { name, kind, file, line } => {
+                    decl_map.insert(name.clone(), Decl { kind, file: file.clone(), line: *line });
+
+                    // redefinition replace old children as well as old digest
+                    child_map.remove(name);
+                }
+                Fact::Inst { name, owner } => {
+                    module_ref.insert(name.clone());
+
+                    match owner {
+                        Some(m) => { child_map.entry(m.clone()).or_default().insert(name.clone()); }
+                        None    => { root_ref.insert(name.clone()); }
+                    }
+                }
+                Fact::Digest { name, digest } => {
+                    if own_map.contains_key(name) { warn!("    {} redefined", name); }
+                    own_map.insert(name.clone(), digest.clone());
+                }
+            }
+        }
+
+        for ((offset, len, line), name, decl) in facts.rename.iter() {
+            rename_map.insert((path.clone(), *offset, *len, *line), (name.clone(), *decl));
+        }
+
+        for ((offset, len, line), name, owner, only) in facts.soft_ref.iter() {
+            soft_ref.push(((path.clone(), *offset, *len, *line), name.clone(), owner.clone(), *only));
+        }
+
+        for (m, u) in facts.port_use.iter() {
+            stub::merge(port_use.entry(m.clone()).or_default(), u);
+        }
+    }
+
+    for (key, name, owner, name_only) in soft_ref.into_iter() {
+        if !own_map.contains_key(&name) { continue; }
+
+        // self reference, e.g. end label
+        if owner.as_ref() != Some(&name) && !name_only {
+            module_ref.insert(name.clone());
+
+            match owner {
+                Some(m) => { child_map.entry(m).or_default().insert(name.clone()); }
+                None    => { root_ref.insert(name.clone()); }
+            }
+        }
+
+        rename_map.insert(key, (name, false));
+    }
+
+    let module_map = resolve_digest(p, &own_map, &child_map)
+        .map_err(|cycle| Error::Rewrite(format!("instantiation cycle: {}", cycle.join(" -> "))))?;
+
+    check_collision(p, &module_map)?;
+
+    Ok(Analysis { module_map, module_ref, root_ref, child_map, decl_map, rename_map, port_use })
+}
+
+// module never instantiated nor referenced become top, checksum is not affected by top set
+pub fn auto_top(p: &mut Parameter, a: &Analysis) -> Result<(), Error> {
+    if !p.auto_top { return Ok(()); }
+
+    let found: BTreeSet<String> = a.module_map.keys()
+        .filter(|m| a.decl_map[*m].kind == "module" && !a.module_ref.contains(*m) && !p.bb_set.contains(*m))
+        .cloned()
+        .collect();
+
+    info!("auto top:");
+    for m in found.iter() {
+        if p.top_set.contains(m) { info!("  {} (given)", m); }
+        else { info!("  {}", m); }
+    }
+
+    for m in p.top_set.iter() {
+        if !found.contains(m) { warn!("  given top {} is instantiated or not declared", m); }
+    }
+
+    p.top_set.extend(found);
+
+    if let Some(n) = p.expect_tops {
+        if p.top_set.len() != n {
+            let list: Vec<&str> = p.top_set.iter().map(|x| x.as_str()).collect();
+            return Err(Error::Rewrite(format!("expect {} top(s) but found {}: {}, give them by -t",
+                                              n, p.top_set.len(), list.join(", "))));
+        }
+    }
+
+    check_collision(p, &a.module_map)
+}
+
+// origin of renamed token in file -> length, old & new name, for renaming `define body
+type MacroRenames = BTreeMap<(PathBuf, usize), (usize, String, String)>;
+
+fn macro_renames(p: &Parameter, a: &Analysis, syntax_tree: &SyntaxTree, path: &str) -> MacroRenames {
+    let mut res: MacroRenames = BTreeMap::new();
+
+    for ((f, offset, len, line), (name, _)) in a.rename_map.range((path.to_string(), 0, 0, 0)..) {
+        if f != path { break; }
+
+        let y = Locate { offset: *offset, len: *len, line: *line };
+
+        if let (Some((f1, o)), Some(n)) = (syntax_tree.get_origin(&y), new_name(p, &a.module_map, name)) {
+            res.insert((f1.clone(), o), (*len, name.clone(), n));
+        }
+    }
+
+    res
+}
+
+// identifier in `define body which renamed token be expanded from, so kept directive agree with expanded text
+fn rename_macro_body(renames: &MacroRenames, syntax_tree: &SyntaxTree, x: &Locate, str: &str) -> String {
+    let (file, start) = match syntax_tree.get_origin(x) {
+        Some((f, o)) => (f.clone(), o),
+        None         => return str.to_string(),
+    };
+
+    let mut edits: BTreeMap<usize, (usize, &String)> = BTreeMap::new();
+
+    // expanded token map to the body 1 byte ahead, see rewrite_source
+    for ((_, o), (len, name, n)) in renames.range((file.clone(), start.saturating_sub(1))..(file, start + str.len())) {
+        let pos = [*o, o + 1].into_iter().filter(|o| *o >= start).map(|o| o - start)
+            .find(|i| str.get(*i..*i + len) == Some(name.as_str()));
+
+        if let Some(i) = pos { edits.insert(i, (*len, n)); }
+    }
+
+    let mut res = String::new();
+    let mut pos = 0;
+
+    for (i, (len, n)) in edits.into_iter() {
+        if i < pos { continue; }
+        res.push_str(&str[pos..i]);
+        res.push_str(n);
+        pos = i + len;
+    }
+
+    res.push_str(&str[pos..]);
+    res
+}
+
+// nothing but whitespace & block comments
+fn only_comments(s: &str) -> bool {
+    let mut rest = s.trim_start();
+
+    while let Some(r) = rest.strip_prefix("/*") {
+        match r.find("*/") {
+            Some(i) => rest = r[i+2..].trim_start(),
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+// original source with identifiers replaced by offset, directives & inactive code kept as is
+fn rewrite_source(p: &Parameter, path: &str, syntax_tree: &SyntaxTree, a: &Analysis,
+                  pruned: &BTreeSet<String>, warns: &mut Vec<String>) -> Result<Option<Output>, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_string(), e))?;
+    let file = std::path::PathBuf::from(path);
+
+    if !scan_directives(&text).0.is_empty() {
+        warns.push(format!("  {} has conditional directives, identifiers in inactive branches are not renamed", path));
+    }
+
+    // offset in original file, only when token come from this file rather than include,
+    // token expanded from macro body map to the body in `define, which is 1 byte ahead
+    let origin = |x: &Locate| -> Option<usize> {
+        match syntax_tree.get_origin(x) {
+            Some((f, off)) if *f == file => [off, off + 1].into_iter()
+                .find(|o| text.get(*o..*o + x.len) == syntax_tree.get_str(x)),
+            _ => None,
+        }
+    };
+
+    // begin, end, replacement
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut first_module: Option<String> = None;
+    let mut decl_end: usize = 0;
+    let mut skipped = false;
+    let mut kept = false;
+
+    for node in syntax_tree {
+        if let Some((_, loc)) = declaration(&node) {
+            if loc.offset < decl_end { continue; }
+            decl_end = node_end(node.clone());
+
+            let name = text_of(syntax_tree, path, &loc)?;
+
+            if pruned.contains(name) {
+                let first = node.clone().into_iter().find_map(|n| if let RefNode::Locate(x) = n { Some(*x) } else { None });
+                let last = last_token(node.clone());
+
+                match (first.and_then(|x| origin(&x)), last.and_then(|x| origin(&x).map(|o| o + x.len))) {
+                    (Some(b), Some(e)) => {
+                        // whitespace & comments before it on the same line, then rest of line after it
+                        let ls = text[..b].rfind('\n').map(|i| i + 1).unwrap_or(0);
+                        let own_line = only_comments(&text[ls..b]);
+                        let b = if own_line { ls } else { b };
+
+                        let e = match text[e..].find('\n') {
+                            Some(n) if own_line && text[e..e+n].trim().is_empty() => e + n + 1,
+                            _ => e,
+                        };
+                        edits.push((b, e, String::new()));
+                        skipped = true;
+                    }
+                    _ => warns.push(format!("  {} in {} is not from source text, can not be pruned", name, path)),
+                }
+                continue;
+            }
+
+            kept = true;
+
+            if first_module.is_none() { first_module = new_name(p, &a.module_map, name); }
+        }
+    }
+
+    let mut done: BTreeSet<usize> = BTreeSet::new();
+
+    for ((f, offset, len, line), (name, _)) in a.rename_map.iter() {
+        if f != path { continue; }
+
+        let x = Locate { offset: *offset, len: *len, line: *line };
+
+        let n = match new_name(p, &a.module_map, name) {
+            Some(n) => n,
+            None    => {
+                if !p.bb_set.contains(name) { warns.push(format!("  unmapped module {}", name)); }
+                continue;
+            }
+        };
+
+        match origin(&x) {
+            Some(off) if edits.iter().any(|(b, e, _)| *b <= off && off < *e) => (),
+            Some(off) => if done.insert(off) { edits.push((off, off + len, n)); },
+            // included file & macro call are kept as is, so they would not match renamed declarations
+            None => match syntax_tree.get_origin(&x) {
+                Some((f, _)) if *f != file => {
+                    return Err(Error::Rewrite(format!("{} at {}:{} come from included {}, which is not renamed \
+                                                       with --preserve-directives", name, path, line, f.display())));
+                }
+                _ => {
+                    return Err(Error::Rewrite(format!("{} at {}:{} come from macro argument, which can not be renamed \
+                                                       with --preserve-directives", name, path, line)));
+                }
+            },
+        }
+    }
+
+    if skipped && !kept { return Ok(None); }
+
+    edits.sort();
+
+    let mut res = String::new();
+    let mut pos = 0;
+
+    for (b, e, n) in edits.into_iter() {
+        if b < pos { continue; }
+        res.push_str(&text[pos..b]);
+        res.push_str(&n);
+        pos = e;
+    }
+
+    res.push_str(&text[pos..]);
+
+    Ok(Some(Output { text: res, first_module }))
+}
+
+pub fn rewrite(p: &Parameter, st_map: &BTreeMap<String, SyntaxTree>, a: &Analysis,
+               cache: &Cache) -> Result<BTreeMap<String, Output>, Vec<Error>> {
+    rewrite_except(p, st_map, a, cache, &BTreeSet::new())
+}
+
+// declarations in `omit` left out like pruned ones, e.g. module shared with other package
+pub(crate) fn rewrite_except(p: &Parameter, st_map: &BTreeMap<String, SyntaxTree>, a: &Analysis, cache: &Cache,
+                             omit: &BTreeSet<String>) -> Result<BTreeMap<String, Output>, Vec<Error>> {
+    let module_map = &a.module_map;
+    let module_ref = &a.module_ref;
+
+    // -------------- 2nd pass -------------
+    info!("rewreite, 2nd pass...");
+
+    if log_enabled!(Level::Debug) {
+        // find unused module
+        for m in module_map.keys() {
+            if !module_ref.contains(m) && !p.top_set.contains(m) {
+                debug!("  unused {} {}", a.decl_map[m].kind, m);
+            }
+        }
+
+        // find unmapped module
+        for m in module_ref.iter() {
+            if !module_map.contains_key(m) && !p.bb_set.contains(m) {
+                debug!("  unmapped module {}", m);
+            }
+        }
+
+        for m in module_map.keys() {
+            debug!("  rename {} -> {}", m, new_name(p, module_map, m).unwrap_or_default());
+        }
+
+    }
+
+    let mut pruned: BTreeSet<String> = if p.prune { unreachable(p, a) } else { BTreeSet::new() };
+
+    if p.prune {
+        if p.top_set.is_empty() { warn!("  top set is empty, everything be pruned"); }
+
+        for m in pruned.iter() {
+            info!("  prune {} {}", a.decl_map[m].kind, m);
+        }
+    }
+
+    pruned.extend(omit.iter().cloned());
+
+    let paths: BTreeSet<&String> = st_map.keys().chain(cache.paths()).collect();
+    let mut res: BTreeMap<String, Output> = BTreeMap::new();
+
+    // output of file with same content & new names reused, others need syntax tree again
+    let mut todo: Vec<(&String, Option<String>)> = Vec::new();
+
+    for path in paths.into_iter() {
+        let key = cache.output_key(p, a, path, &pruned);
+
+        match key.as_ref().and_then(|k| cache.output(k)) {
+            Some((o, warns)) => {
+                info!("  {} (cached)", path);
+                replay_unused(p, a, path, &pruned);
+                for w in warns.iter() { warn!("{}", w); }
+                done(p, path, o, &mut res);
+            }
+            None => todo.push((path, key)),
+        }
+    }
+
+    let reparse: Vec<String> = todo.iter().filter(|(f, _)| !st_map.contains_key(*f)).map(|(f, _)| f.to_string()).collect();
+    let reparsed = parse_list(p, &reparse)?;
+
+    for (path, key) in todo.into_iter() {
+        let syntax_tree = st_map.get(path).or_else(|| reparsed.get(path)).unwrap();
+
+        let mut warns: Vec<String> = Vec::new();
+
+        let o = if p.preserve_directives { rewrite_source(p, path, syntax_tree, a, &pruned, &mut warns)? }
+                else { rewrite_file(p, path, syntax_tree, a, &pruned, &mut warns)? };
+
+        for w in warns.iter() { warn!("{}", w); }
+
+        if let Some(k) = key { cache.store_output(&k, o.as_ref(), &warns); }
+        done(p, path, o, &mut res);
+    }
+
+    Ok(res)
+}
+
+// none for file with every declaration left out
+fn done(p: &Parameter, path: &str, o: Option<Output>, res: &mut BTreeMap<String, Output>) {
+    match o {
+        Some(o) => { res.insert(path.to_string(), o); },
+        None if p.prune => info!("  prune file {}", path),
+        None => debug!("  omit file {}", path),
+    }
+}
+
+// unused declarations of file with output taken from cache
+fn replay_unused(p: &Parameter, a: &Analysis, path: &str, pruned: &BTreeSet<String>) {
+    for ((f, _, _, _), (name, decl)) in a.rename_map.iter() {
+        if f != path { continue; }
+
+        if *decl && !pruned.contains(name) && a.module_map.contains_key(name) && !a.module_ref.contains(name) && !p.top_set.contains(name) {
+            info!("  unused {} {}", a.decl_map[name].kind, name);
+        }
+    }
+}
+
+fn rewrite_file(p: &Parameter, path: &str, syntax_tree: &SyntaxTree, a: &Analysis,
+                pruned: &BTreeSet<String>, warns: &mut Vec<String>) -> Result<Option<Output>, Error> {
+    let module_map = &a.module_map;
+    let module_ref = &a.module_ref;
+    let rename_map = &a.rename_map;
+
+    let mut e = Emitter::new();
+    let mut first_module: Option<String> = None;
+
+    // top level declaration, pruned one be skipped till its last token
+    let mut decl_end: usize = 0;
+    let mut skip_end: usize = 0;
+    let mut skipped = false;
+    let mut trim_line = false;
+    let mut kept = false;
+
+    let mut space_set: BTreeSet<Loc> = BTreeSet::new();
+    let mut comment_set: BTreeSet<Loc> = BTreeSet::new();
+    let mut directive_end: usize = 0;
+    let mut renames: Option<MacroRenames> = None;
+
+    // leading comment block, end by code or blank line
+    let mut header_open = true;
+    let mut header_seen = false;
+    let mut newlines: usize = 0;
+
+    for node in syntax_tree {
+        if let RefNode::WhiteSpace(x) = node {
+            match x {
+                WhiteSpace::Space(l)    => { space_set.insert((l.offset, l.len, l.line)); },
+                WhiteSpace::Comment(c)  => { comment_set.insert((c.nodes.0.offset, c.nodes.0.len, c.nodes.0.line)); },
+                WhiteSpace::CompilerDirective(d) => {
+                    directive_end = directive_end.max(node_end(node.clone()));
+
+                    // already applied to following text
+                    if p.preprocessed && matches!(**d, CompilerDirective::TextMacroDefinition(_) |
+                                                         CompilerDirective::UndefineCompilerDirective(_) |
+                                                         CompilerDirective::UndefineallCompilerDirective(_)) {
+                        skip_end = skip_end.max(node_end(node.clone()));
+                        trim_line = true;
+                    }
+                },
+            }
+        }
+
+        if let Some((kind, loc)) = declaration(&node) {
+            let name = text_of(syntax_tree, path, &loc)?;
+
+            if loc.offset >= decl_end {
+                decl_end = node_end(node.clone());
+
+                if pruned.contains(name) {
+                    skip_end = code_end(node.clone());
+                    skipped = true;
+                    trim_line = e.drop_line();
+                    continue;
+                }
+
+                kept = true;
+            }
+
+            if module_map.contains_key(name) && !module_ref.contains(name) && !p.top_set.contains(name) {
+                info!("  unused {} {}", kind, name);
+            }
+
+            if first_module.is_none() {
+                first_module = new_name(p, module_map, name);
+            }
+        }
+
+        if let RefNode::Locate(x) = node {
+            let str = text_of(syntax_tree, path, x)?;
+            let loc = (path.to_string(), x.offset, x.len, x.line);
+
+            // `//` comment include its newline, so blank line may be split across comment & whitespace
+            let k = (x.offset, x.len, x.line);
+            if comment_set.contains(&k) {
+                header_seen = true;
+                newlines = if str.ends_with('\n') { 1 } else { 0 };
+            }
+            else if space_set.contains(&k) {
+                newlines += str.matches('\n').count();
+                if header_seen && newlines > 1 { header_open = false; }
+            }
+            else { header_open = false; }
+
+            if x.offset < skip_end { continue; }
+
+            // whitespace & comment till end of line after pruned declaration
+            let str = if trim_line {
+                let k = (x.offset, x.len, x.line);
+
+                if !space_set.contains(&k) && !comment_set.contains(&k) { trim_line = false; str }
+                else {
+                    match str.split_once('\n') {
+                        Some((_, "")) => { trim_line = false; continue },
+                        Some((_, r))  => { trim_line = false; r },
+                        None         => continue,
+                    }
+                }
+            }
+            else { str };
+
+            if comment_set.contains(&(x.offset, x.len, x.line)) {
+                let keep = !(p.strip_comments || p.minify) || (p.keep_header && header_open);
+                e.comment(p, str, keep);
+            }
+            else if space_set.contains(&(x.offset, x.len, x.line)) {
+                e.whitespace(p, str);
+            }
+            else {
+                let directive = x.offset < directive_end;
+
+                if rename_map.contains_key(&loc) {
+                    match new_name(p, module_map, str) {
+                        Some(n) => e.token(p, &n, directive),
+                        None    => {
+                            if !p.bb_set.contains(str) { warns.push(format!("  unmapped module {}", str)); }
+                            e.token(p, str, directive)
+                        }
+                    }
+                }
+                else if directive {
+                    let renames = renames.get_or_insert_with(|| macro_renames(p, a, syntax_tree, path));
+                    e.token(p, &rename_macro_body(renames, syntax_tree, x, str), directive)
+                }
+                else {
+                    e.token(p, str, directive)
+                }
+            }
+        }
+    }
+
+    if skipped && !kept { return Ok(None); }
+
+    if p.minify && !e.text.is_empty() { e.push("\n"); }
+
+    let text = e.finish(p);
+    Ok(Some(Output { text, first_module }))
+}
+
+pub(crate) fn get_identifier(node: RefNode) -> Option<Locate> {
+    // unwrap_node! can take multiple types
+    match unwrap_node!(node, SimpleIdentifier, EscapedIdentifier) {
+        Some(RefNode::SimpleIdentifier(x)) => {
+            Some(x.nodes.0)
+        }
+        Some(RefNode::EscapedIdentifier(x)) => {
+            Some(x.nodes.0)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::resolve_digest;
+    use crate::hash::{Checksum, HashAlgo, Hasher};
+    use crate::param::Parameter;
+
+    fn digest(s: &str) -> Checksum {
+        let mut h = Hasher::new(HashAlgo::Crc32);
+        h.update(s.as_bytes());
+        h.finalize()
+    }
+
+    #[test]
+    fn leaf_keep_own_digest() {
+        let own: BTreeMap<String, Checksum> = ["leaf", "empty", "top"].iter().map(|m| (m.to_string(), digest(m))).collect();
+        let mut child: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        child.entry("top".to_string()).or_default().extend(["leaf".to_string(), "empty".to_string()]);
+        child.entry("empty".to_string()).or_default();
+
+        let res = resolve_digest(&Parameter::new(), &own, &child).unwrap();
+        assert_eq!(res["leaf"], own["leaf"]);
+        assert_eq!(res["empty"], own["empty"]);
+        assert_ne!(res["top"], own["top"]);
+
+        let res = resolve_digest(&Parameter::new().legacy_hash(), &own, &child).unwrap();
+        assert_eq!(res, own);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use log::{info, warn};
+use sv_parser::{parse_sv, unwrap_node, Locate, RefNode, SyntaxTree};
+
+use crate::error::Error;
+use crate::param::Parameter;
+use crate::rewrite::{get_identifier, last_token, Analysis};
+use crate::to_defines;
+
+// ports & parameters seen in instantiations of a module
+#[derive(Default)]
+pub struct PortUse {
+    pub kind: &'static str,
+    pub ports: Vec<String>,
+    pub ordered_ports: usize,
+    pub params: Vec<String>,
+    pub ordered_params: usize,
+}
+
+pub(crate) fn record(map: &mut BTreeMap<String, PortUse>, name: &str, node: &RefNode, syntax_tree: &SyntaxTree) {
+    let u = map.entry(name.to_string()).or_default();
+
+    u.kind = match node {
+        RefNode::InterfaceInstantiation(_) => "interface",
+        RefNode::ProgramInstantiation(_)   => "program",
+        _                                  => "module",
+    };
+
+    let push = |v: &mut Vec<String>, n: RefNode| {
+        if let Some(s) = get_identifier(n).and_then(|l| syntax_tree.get_str(&l)) {
+            if !v.iter().any(|x| x == s) { v.push(s.to_string()); }
+        }
+    };
+
+    let mut ordered_params = 0;
+
+    for n in node.clone().into_iter() {
+        match n {
+            RefNode::NamedPortConnectionIdentifier(x) => push(&mut u.ports, (&x.nodes.2).into()),
+            RefNode::NamedParameterAssignment(x)      => push(&mut u.params, (&x.nodes.1).into()),
+            RefNode::OrderedParameterAssignment(_)    => ordered_params += 1,
+            RefNode::HierarchicalInstance(x) => {
+                // `()` is a single empty ordered connection
+                let conns: Vec<_> = x.into_iter().filter_map(|c| match c {
+                    RefNode::OrderedPortConnection(o) => Some(o.nodes.1.is_some()),
+                    _ => None,
+                }).collect();
+
+                let n = if conns == [false] { 0 } else { conns.len() };
+                u.ordered_ports = u.ordered_ports.max(n);
+            }
+            _ => (),
+        }
+    }
+
+    u.ordered_params = u.ordered_params.max(ordered_params);
+}
+
+// combine ports & parameters seen in another file
+pub(crate) fn merge(u: &mut PortUse, other: &PortUse) {
+    u.kind = other.kind;
+
+    for x in other.ports.iter() {
+        if !u.ports.contains(x) { u.ports.push(x.clone()); }
+    }
+
+    for x in other.params.iter() {
+        if !u.params.contains(x) { u.params.push(x.clone()); }
+    }
+
+    u.ordered_ports = u.ordered_ports.max(other.ordered_ports);
+    u.ordered_params = u.ordered_params.max(other.ordered_params);
+}
+
+fn text_of(syntax_tree: &SyntaxTree, node: RefNode) -> Option<String> {
+    let first = node.clone().into_iter().find_map(|n| if let RefNode::Locate(x) = n { Some(*x) } else { None })?;
+    let last = last_token(node)?;
+    let len = last.offset + last.len - first.offset;
+
+    syntax_tree.get_str(&Locate { offset: first.offset, line: first.line, len }).map(|x| x.to_string())
+}
+
+// header & port declarations copied from declarations in header file
+fn read_header(p: &Parameter, path: &str) -> Result<BTreeMap<String, String>, Error> {
+    let defines = to_defines(&p.defines);
+    let syntax_tree = match parse_sv(path, &defines, &p.inc_list, false, false) {
+        Ok((x, _)) => x,
+        Err(e) => return Err(Error::from_sv(path, e)),
+    };
+
+    let mut res: BTreeMap<String, String> = BTreeMap::new();
+
+    for node in &syntax_tree {
+        let (id, end) = match node {
+            RefNode::ModuleDeclarationAnsi(x)       => (unwrap_node!(x, ModuleIdentifier), "endmodule"),
+            RefNode::ModuleDeclarationNonansi(x)    => (unwrap_node!(x, ModuleIdentifier), "endmodule"),
+            RefNode::InterfaceDeclarationAnsi(x)    => (unwrap_node!(x, InterfaceIdentifier), "endinterface"),
+            RefNode::InterfaceDeclarationNonansi(x) => (unwrap_node!(x, InterfaceIdentifier), "endinterface"),
+            _ => continue,
+        };
+
+        let name = match id.and_then(get_identifier).and_then(|l| syntax_tree.get_str(&l)) {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+
+        let header = match unwrap_node!(node.clone(), ModuleAnsiHeader, ModuleNonansiHeader, InterfaceAnsiHeader, InterfaceNonansiHeader) {
+            Some(h) => h,
+            None => continue,
+        };
+
+        let mut s = text_of(&syntax_tree, header).unwrap_or_default();
+        s.push('\n');
+
+        // non-ANSI port declarations in body
+        for n in node.clone().into_iter() {
+            if let RefNode::PortDeclaration(_) = n {
+                if let Some(t) = text_of(&syntax_tree, n) { s.push_str(&format!("  {};\n", t)); }
+            }
+        }
+
+        s.push_str(end);
+        s.push('\n');
+
+        res.insert(name, s);
+    }
+
+    Ok(res)
+}
+
+// module without body, ports inferred from instantiations, direction & width unknown
+fn infer(name: &str, u: Option<&PortUse>) -> String {
+    let empty = PortUse { kind: "module", ..PortUse::default() };
+    let u = u.unwrap_or(&empty);
+
+    let mut params: Vec<String> = u.params.clone();
+    for i in params.len()..u.ordered_params { params.push(format!("P{}", i)); }
+
+    let mut ports: Vec<String> = u.ports.clone();
+    if ports.is_empty() {
+        ports = (0..u.ordered_ports).map(|i| format!("p{}", i)).collect();
+    }
+    else if u.ordered_ports > 0 {
+        warn!("  {} has both named and ordered connections, ordered ones ignored", name);
+    }
+
+    let mut s = format!("{} {}", u.kind, name);
+
+    if !params.is_empty() {
+        let list: Vec<String> = params.iter().map(|x| format!("parameter {} = 0", x)).collect();
+        s.push_str(&format!(" #({})", list.join(", ")));
+    }
+
+    if !ports.is_empty() {
+        let list: Vec<String> = ports.iter().map(|x| format!("\n  inout wire {}", x)).collect();
+        s.push_str(&format!(" ({}\n)", list.join(",")));
+    }
+
+    s.push_str(&format!(";\nend{}\n", u.kind));
+    s
+}
+
+pub fn write_stubs(p: &Parameter, a: &Analysis) -> Result<(), Error> {
+    let path = match &p.stub {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let header = match &p.stub_header {
+        Some(h) => read_header(p, h)?,
+        None => BTreeMap::new(),
+    };
+
+    // blackbox & unmapped module, declared one already in output
+    let mut names: BTreeSet<&String> = p.bb_set.iter().collect();
+    names.extend(a.module_ref.iter());
+    names.retain(|m| !a.module_map.contains_key(*m));
+
+    if Path::new(path).exists() && !p.force {
+        return Err(Error::Output(format!("{} already exist, use --force to overwrite", path)));
+    }
+
+    info!("write stub {}", path);
+
+    let mut s = String::from("// stub of blackbox & unmapped modules\n");
+
+    for m in names.iter() {
+        s.push('\n');
+
+        match header.get(*m) {
+            Some(h) => {
+                info!("  {} (header)", m);
+                s.push_str(h);
+            }
+            None => {
+                info!("  {} (inferred)", m);
+                s.push_str(&infer(m, a.port_use.get(*m)));
+            }
+        }
+    }
+
+    fs::write(path, s).map_err(|e| Error::Io(path.to_string(), e))
+}
@@ -0,0 +1,253 @@
+use crate::hash::Checksum;
+use crate::param::Parameter;
+
+pub const TOP_TEMPLATE_DEFAULT: &str = "{name}_r{rev}";
+pub const MODULE_TEMPLATE_DEFAULT: &str = "{name}_{hash}";
+
+// IEEE 1800-2017 Annex B
+const KEYWORDS: &[&str] = &[
+    "accept_on", "alias", "always", "always_comb", "always_ff", "always_latch", "and", "assert", "assign",
+    "assume", "automatic", "before", "begin", "bind", "bins", "binsof", "bit", "break", "buf", "bufif0",
+    "bufif1", "byte", "case", "casex", "casez", "cell", "chandle", "checker", "class", "clocking", "cmos",
+    "config", "const", "constraint", "context", "continue", "cover", "covergroup", "coverpoint", "cross",
+    "deassign", "default", "defparam", "design", "disable", "dist", "do", "edge", "else", "end", "endcase",
+    "endchecker", "endclass", "endclocking", "endconfig", "endfunction", "endgenerate", "endgroup",
+    "endinterface", "endmodule", "endpackage", "endprimitive", "endprogram", "endproperty", "endspecify",
+    "endsequence", "endtable", "endtask", "enum", "event", "eventually", "expect", "export", "extends",
+    "extern", "final", "first_match", "for", "force", "foreach", "forever", "fork", "forkjoin", "function",
+    "generate", "genvar", "global", "highz0", "highz1", "if", "iff", "ifnone", "ignore_bins",
+    "illegal_bins", "implements", "implies", "import", "incdir", "include", "initial", "inout", "input",
+    "inside", "instance", "int", "integer", "interconnect", "interface", "intersect", "join", "join_any",
+    "join_none", "large", "let", "liblist", "library", "local", "localparam", "logic", "longint",
+    "macromodule", "matches", "medium", "modport", "module", "nand", "negedge", "nettype", "new", "nexttime",
+    "nmos", "nor", "noshowcancelled", "not", "notif0", "notif1", "null", "or", "output", "package", "packed",
+    "parameter", "pmos", "posedge", "primitive", "priority", "program", "property", "protected", "pull0",
+    "pull1", "pulldown", "pullup", "pulsestyle_ondetect", "pulsestyle_onevent", "pure", "rand", "randc",
+    "randcase", "randsequence", "rcmos", "real", "realtime", "ref", "reg", "reject_on", "release", "repeat",
+    "restrict", "return", "rnmos", "rpmos", "rtran", "rtranif0", "rtranif1", "s_always", "s_eventually",
+    "s_nexttime", "s_until", "s_until_with", "scalared", "sequence", "shortint", "shortreal",
+    "showcancelled", "signed", "small", "soft", "solve", "specify", "specparam", "static", "string",
+    "strong", "strong0", "strong1", "struct", "super", "supply0", "supply1", "sync_accept_on",
+    "sync_reject_on", "table", "tagged", "task", "this", "throughout", "time", "timeprecision", "timeunit",
+    "tran", "tranif0", "tranif1", "tri", "tri0", "tri1", "triand", "trior", "trireg", "type", "typedef",
+    "union", "unique", "unique0", "unsigned", "until", "until_with", "untyped", "use", "uwire", "var",
+    "vectored", "virtual", "void", "wait", "wait_order", "wand", "weak", "weak0", "weak1", "while",
+    "wildcard", "wire", "with", "within", "wor", "xnor", "xor",
+];
+
+#[derive(PartialEq, Clone, Debug)]
+enum Piece {
+    Text(String),
+    Name,
+    Pkg,
+    Rev,
+    // width, uppercase
+    Hash(Option<usize>, bool),
+}
+
+fn parse(tpl: &str, max_width: usize) -> Result<Vec<Piece>, String> {
+    let mut res: Vec<Piece> = Vec::new();
+    let mut text = String::new();
+    let mut rest = tpl;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            text.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+
+        if c == '}' { return Err(format!("unmatched '}}' in template '{}'", tpl)); }
+
+        if c != '{' {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let end = rest.find('}').ok_or_else(|| format!("unmatched '{{' in template '{}'", tpl))?;
+        let field = &rest[1..end];
+        rest = &rest[end+1..];
+
+        if !text.is_empty() { res.push(Piece::Text(std::mem::take(&mut text))); }
+
+        let (key, width) = match field.split_once(':') {
+            None => (field, None),
+            Some((k, w)) => {
+                let w: usize = w.parse().map_err(|_| format!("invalid width '{}' in template '{}'", w, tpl))?;
+                if w == 0 || w > max_width {
+                    return Err(format!("width {} in template '{}' should between 1 and {}", w, tpl, max_width));
+                }
+                (k, Some(w))
+            }
+        };
+
+        res.push(match (key, width) {
+            ("name", None) => Piece::Name,
+            ("pkg",  None) => Piece::Pkg,
+            ("rev",  None) => Piece::Rev,
+            ("hash", w)    => Piece::Hash(w, false),
+            ("HASH", w)    => Piece::Hash(w, true),
+            _ => return Err(format!("unknown placeholder '{{{}}}' in template '{}'", field, tpl)),
+        });
+    }
+
+    if !text.is_empty() { res.push(Piece::Text(text)); }
+
+    Ok(res)
+}
+
+pub(crate) fn check(p: &Parameter) -> Result<(), String> {
+    parse(&p.top_template, p.hash.max_width())?;
+
+    let pieces = parse(&p.module_template, p.hash.max_width())?;
+    if !pieces.iter().any(|x| matches!(x, Piece::Hash(_, _))) {
+        return Err(format!("module template '{}' has no {{hash}}, renamed modules would not be unique", p.module_template));
+    }
+
+    Ok(())
+}
+
+// hex characters of checksum in names from module template, widest one when given more than once
+pub(crate) fn module_hash_width(p: &Parameter) -> usize {
+    parse(&p.module_template, p.hash.max_width()).unwrap_or_default().iter()
+        .filter_map(|x| match x {
+            Piece::Hash(w, _) => Some(w.unwrap_or_else(|| p.suffix_width())),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_else(|| p.suffix_width())
+}
+
+fn is_simple_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$') && !KEYWORDS.contains(&s)
+}
+
+fn expand(pieces: &[Piece], p: &Parameter, name: &str, cksum: Option<&Checksum>) -> String {
+    let mut res = String::new();
+
+    for x in pieces.iter() {
+        match x {
+            Piece::Text(t) => res.push_str(t),
+            Piece::Name    => res.push_str(name),
+            Piece::Pkg     => res.push_str(&p.pkg),
+            Piece::Rev     => res.push_str(&p.rev.to_string()),
+            Piece::Hash(w, upper) => {
+                let h = cksum.map(|c| c.suffix(w.unwrap_or_else(|| p.suffix_width()))).unwrap_or_default();
+                res.push_str(&if *upper { h.to_uppercase() } else { h });
+            }
+        }
+    }
+
+    res
+}
+
+// new name for `name`, escaped when not a legal simple identifier
+pub(crate) fn render(p: &Parameter, top: bool, name: &str, cksum: Option<&Checksum>) -> Result<String, String> {
+    let tpl = if top { &p.top_template } else { &p.module_template };
+    let pieces = parse(tpl, p.hash.max_width())?;
+
+    // escaped identifier keep its backslash out of the template
+    let bare = name.strip_prefix('\\').unwrap_or(name);
+    let res = expand(&pieces, p, bare, cksum);
+
+    if is_simple_identifier(&res) { return Ok(res); }
+
+    if res.is_empty() || res.chars().any(|c| !c.is_ascii_graphic()) {
+        return Err(format!("'{}' generated from template '{}' is not a legal identifier", res, tpl));
+    }
+
+    Ok(format!("\\{} ", res))
+}
+
+// recover checksum hex from a name generated by module template, e.g. for verify
+pub(crate) fn extract_hash(p: &Parameter, name: &str, generated: &str) -> Option<String> {
+    let pieces = parse(&p.module_template, p.hash.max_width()).ok()?;
+    let idx = pieces.iter().position(|x| matches!(x, Piece::Hash(_, _)))?;
+
+    let width = match &pieces[idx] {
+        Piece::Hash(w, _) => w.unwrap_or_else(|| p.suffix_width()),
+        _ => return None,
+    };
+
+    let bare = name.strip_prefix('\\').unwrap_or(name);
+    let prefix = expand(&pieces[..idx], p, bare, None);
+    let suffix = expand(&pieces[idx+1..], p, bare, None);
+
+    let generated = generated.strip_prefix('\\').unwrap_or(generated).trim_end();
+    let hash = generated.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+
+    if hash.len() == width && hash.chars().all(|c| c.is_ascii_hexdigit()) { Some(hash.to_lowercase()) }
+    else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, extract_hash, render};
+    use crate::hash::Checksum;
+    use crate::param::Parameter;
+
+    const CKSUM: Checksum = Checksum::Crc32(0x1234abcd);
+
+    fn p(top: &str, module: &str) -> Parameter {
+        Parameter::new().pkg("soc").rev(3).templates(top, module)
+    }
+
+    #[test]
+    fn placeholders() {
+        let p = p("{name}_{pkg}_r{rev}", "{pkg}_{name}_{hash:4}_{HASH}");
+
+        assert_eq!(render(&p, true, "top", None).unwrap(), "top_soc_r3");
+        assert_eq!(render(&p, false, "leaf", Some(&CKSUM)).unwrap(), "soc_leaf_1234_1234ABCD");
+    }
+
+    #[test]
+    fn brace_escape() {
+        let t = p("{name}{{r{rev}}}", "{name}_{hash}");
+        assert_eq!(render(&t, true, "top", None).unwrap(), "\\top{r3} ");
+
+        for bad in ["{name", "{name}}x{", "x}", "{nam}", "{hash:0}", "{hash:9}", "{hash:x}"] {
+            assert!(check(&p(bad, "{name}_{hash}")).is_err(), "{}", bad);
+        }
+
+        assert!(check(&p("{name}", "{name}_{rev}")).is_err());
+    }
+
+    #[test]
+    fn escape_keyword_and_illegal_name() {
+        let p = p("{name}", "{name}{hash}");
+
+        // keyword, leading digit and escaped name all give escaped identifier
+        assert_eq!(render(&p, true, "module", None).unwrap(), "\\module ");
+        assert_eq!(render(&Parameter::new().templates("{rev}_{name}", "{name}_{hash}"), true, "t", None).unwrap(), "\\0_t ");
+        assert_eq!(render(&p, true, "\\a+b", None).unwrap(), "\\a+b ");
+        assert_eq!(render(&p, false, "\\a.b", Some(&CKSUM)).unwrap(), "\\a.b1234abcd ");
+        assert_eq!(render(&p, true, "ok$1", None).unwrap(), "ok$1");
+
+        let p = Parameter::new().templates("{name} x", "{name}_{hash}");
+        assert!(render(&p, true, "top", None).is_err());
+    }
+
+    #[test]
+    fn extract_hash_from_name() {
+        let p = p("{name}_r{rev}", "{pkg}_{name}_{HASH:6}_x");
+
+        let n = render(&p, false, "leaf", Some(&CKSUM)).unwrap();
+        assert_eq!(n, "soc_leaf_1234AB_x");
+        assert_eq!(extract_hash(&p, "leaf", &n).as_deref(), Some("1234ab"));
+
+        let n = render(&p, false, "\\a.b", Some(&CKSUM)).unwrap();
+        assert_eq!(extract_hash(&p, "\\a.b", &n).as_deref(), Some("1234ab"));
+
+        assert_eq!(extract_hash(&p, "leaf", "soc_leaf_1234_x"), None);
+        assert_eq!(extract_hash(&p, "leaf", "soc_other_1234AB_x"), None);
+        assert_eq!(extract_hash(&p, "leaf", "soc_leaf_12345G_x"), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use log::{info, warn};
+use serde_json::Value;
+use sv_parser::{parse_sv, Defines};
+
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::hash::{Checksum, HashAlgo};
+use crate::param::{show_info, Parameter, PKG_DEFAULT, REV_DEFAULT};
+use crate::pattern::resolve_patterns;
+use crate::rewrite::{analyze, auto_top, declaration, text_of, unreachable};
+use crate::template::{self, MODULE_TEMPLATE_DEFAULT, TOP_TEMPLATE_DEFAULT};
+use crate::parse_files;
+
+// expected checksum hex of every module, may be truncated, None for top which has no checksum in name
+type Expect = BTreeMap<String, Option<String>>;
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// read earlier manifest, also take package, revision & defines from it when not given
+fn read_manifest(p: &mut Parameter, path: &str) -> Result<Expect, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_string(), e))?;
+    let json: Value = serde_json::from_str(&text).map_err(|e| Error::Verify(format!("{}: {}", path, e)))?;
+
+    if p.pkg == PKG_DEFAULT {
+        if let Some(x) = json.get("package").and_then(|x| x.as_str()) { p.pkg = x.to_string(); }
+    }
+
+    if p.rev == REV_DEFAULT {
+        if let Some(x) = json.get("revision").and_then(|x| x.as_u64()) { p.rev = x as usize; }
+    }
+
+    if p.hash == HashAlgo::Crc32 && p.hash_width.is_none() {
+        if let Some(x) = json.get("hash").and_then(|x| x.as_str()).and_then(HashAlgo::from_name) { p.hash = x; }
+        if let Some(x) = json.get("hash_width").and_then(|x| x.as_u64()) { p.hash_width = Some(x as usize); }
+    }
+
+    // manifest before canonical token hash has no such field
+    if !p.legacy_hash {
+        p.legacy_hash = json.get("token_hash").and_then(|x| x.as_str()).unwrap_or("legacy") == "legacy";
+    }
+
+    if !p.content_addressed {
+        p.content_addressed = json.get("content_addressed").and_then(|x| x.as_bool()).unwrap_or(false);
+    }
+
+    if p.top_template == TOP_TEMPLATE_DEFAULT {
+        if let Some(x) = json.get("top_template").and_then(|x| x.as_str()) { p.top_template = x.to_string(); }
+    }
+
+    if p.module_template == MODULE_TEMPLATE_DEFAULT {
+        if let Some(x) = json.get("module_template").and_then(|x| x.as_str()) { p.module_template = x.to_string(); }
+    }
+
+    if p.defines.is_empty() {
+        if let Some(m) = json.get("defines").and_then(|x| x.as_object()) {
+            for (k, v) in m.iter() {
+                p.defines.insert(k.clone(), v.as_str().map(|x| x.to_string()));
+            }
+        }
+    }
+
+    let mut res: Expect = BTreeMap::new();
+
+    if let Some(v) = json.get("modules").and_then(|x| x.as_array()) {
+        for m in v.iter() {
+            let name = match m.get("name").and_then(|x| x.as_str()) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // undeclared module has no checksum
+            if let Some(c) = m.get("checksum").and_then(|x| x.as_str()) {
+                if !is_hex(c) {
+                    return Err(Error::Verify(format!("{}: bad checksum {} of {}", path, c, name)));
+                }
+                res.insert(name.to_string(), Some(c.to_string()));
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// declared names in renamed output
+fn read_released(path: &str) -> Result<BTreeSet<String>, Vec<Error>> {
+    fn walk(p: &Path, res: &mut Vec<PathBuf>) -> Result<(), Error> {
+        if p.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(p).map_err(|e| Error::Io(p.display().to_string(), e))?
+                .filter_map(|e| e.ok().map(|x| x.path()))
+                .collect();
+            entries.sort();
+
+            for e in entries.iter() { walk(e, res)?; }
+        }
+        else if matches!(p.extension().and_then(|x| x.to_str()), Some("sv") | Some("v") | Some("svh") | Some("vh")) {
+            res.push(p.to_path_buf());
+        }
+
+        Ok(())
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    walk(Path::new(path), &mut files)?;
+
+    let mut res: BTreeSet<String> = BTreeSet::new();
+    let mut errs: Vec<Error> = Vec::new();
+    let defines: Defines = HashMap::new();
+    let no_inc: Vec<PathBuf> = Vec::new();
+
+    for f in files.iter() {
+        let syntax_tree = match parse_sv(f, &defines, &no_inc, false, false) {
+            Ok((x, _)) => x,
+            Err(e) => { errs.push(Error::from_sv(&f.display().to_string(), e)); continue; }
+        };
+
+        let path = f.display().to_string();
+
+        for node in &syntax_tree {
+            if let Some((_, loc)) = declaration(&node) {
+                match text_of(&syntax_tree, &path, &loc) {
+                    Ok(x)  => { res.insert(x.to_string()); }
+                    Err(e) => errs.push(e),
+                }
+            }
+        }
+    }
+
+    if errs.is_empty() { Ok(res) } else { Err(errs) }
+}
+
+// recover original name & checksum by matching released names with templates,
+// released name match no module is kept as is and reported disappeared
+fn match_released(p: &Parameter, module_map: &BTreeMap<String, Checksum>, released: BTreeSet<String>) -> Expect {
+    let mut rest = released;
+    let mut res: Expect = BTreeMap::new();
+
+    for (m, cksum) in module_map.iter() {
+        if p.top_set.contains(m) {
+            if let Ok(n) = template::render(p, true, m, Some(cksum)) {
+                if rest.remove(n.trim_end()) { res.insert(m.clone(), None); }
+            }
+            continue;
+        }
+
+        let found = rest.iter().find_map(|r| template::extract_hash(p, m, r).map(|h| (r.clone(), h)));
+
+        if let Some((r, h)) = found {
+            rest.remove(&r);
+            res.insert(m.clone(), Some(h));
+        }
+    }
+
+    for r in rest.into_iter() { res.insert(r, None); }
+
+    res
+}
+
+pub fn verify(mut p: Parameter) -> Result<(), Vec<Error>> {
+    let (expect, released) = match (p.manifest.clone(), &p.against) {
+        (Some(m), _) => { info!("verify against manifest {}", m); (read_manifest(&mut p, &m)?, None) },
+        (None, Some(a)) => { info!("verify against release {}", a); (BTreeMap::new(), Some(read_released(a)?)) },
+        (None, None) => return Err(Error::Arg("verify need --manifest or --against".to_string()).into()),
+    };
+
+    show_info(&p);
+
+    let mut cache = Cache::open(&p);
+    let syntax_tree_map = parse_files(&p, &cache)?;
+    let analysis = analyze(&p, &syntax_tree_map, &mut cache)?;
+    resolve_patterns(&mut p, &analysis)?;
+    auto_top(&mut p, &analysis)?;
+
+    // pruned module is absent from release
+    let pruned = if p.prune && released.is_some() { unreachable(&p, &analysis) } else { BTreeSet::new() };
+
+    let expect = match released {
+        Some(r) => match_released(&p, &analysis.module_map, r),
+        None    => expect,
+    };
+
+    let mut drift = 0;
+    let mut unverified = 0;
+
+    info!("verify result:");
+
+    for (m, exp) in expect.iter() {
+        match (exp, analysis.module_map.get(m)) {
+            (_, None) => {
+                warn!("  disappeared {}", m);
+                drift += 1;
+            }
+            (None, Some(_)) => {
+                warn!("  unverified  {} (top, no checksum in name)", m);
+                unverified += 1;
+            }
+            (Some(e), Some(c)) if c.suffix(e.len()) == *e => info!("  match       {} {}", m, e),
+            (Some(e), Some(c)) => {
+                warn!("  changed     {} {} -> {}", m, e, c.suffix(e.len()));
+                drift += 1;
+            }
+        }
+    }
+
+    for (m, c) in analysis.module_map.iter() {
+        if !expect.contains_key(m) && !pruned.contains(m) {
+            warn!("  new         {} {}", m, c);
+            drift += 1;
+        }
+    }
+
+    if drift > 0 {
+        return Err(Error::Verify(format!("{} module(s) drift from previous release", drift)).into());
+    }
+
+    // body of top not covered by release names, only a manifest has its checksum
+    if unverified > 0 {
+        warn!("{} module(s) match, {} top(s) unverified, use --manifest to verify tops",
+              expect.len() - unverified, unverified);
+    }
+    else {
+        info!("all {} module(s) match", expect.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::fs;
+
+use shim_release::{parse_args, release, verify, write_output, Error, HashAlgo, Parameter};
+
+fn temp_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("shim-release-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn args(v: &[&str]) -> Vec<String> {
+    v.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn parse_error_excerpt() {
+    let dir = temp_dir("parse-error");
+    let src = dir.join("a.sv");
+    fs::write(&src, "module m;\n  wire x\nendmodule\n").unwrap();
+
+    let mut p = Parameter::new().file(src.to_string_lossy().to_string());
+    let err = release(&mut p).err().expect("no error");
+    fs::remove_dir_all(&dir).unwrap();
+
+    let (file, pos, excerpt) = match &err[0] {
+        Error::Parse { file, pos, excerpt, .. } => (file, pos, excerpt),
+        e => panic!("not parse error: {}", e),
+    };
+
+    assert!(file.ends_with("a.sv"));
+    assert_eq!(*pos, Some((3, 1)));
+    assert_eq!(excerpt, "3 | endmodule\n  | ^");
+    assert!(err[0].to_string().contains("a.sv:3:1: syntax error\n3 | endmodule"));
+    assert_eq!(err[0].exit_code(), 4);
+}
+
+#[test]
+fn exit_code_per_class() {
+    assert_eq!(parse_args(args(&["--pruen", "a.sv"])).unwrap_err().exit_code(), 2);
+
+    let mut p = Parameter::new().file("/nonexistent/shim-release/a.sv");
+    assert_eq!(release(&mut p).err().unwrap()[0].exit_code(), 3);
+
+    let dir = temp_dir("exit-code");
+    let src = dir.join("a.sv");
+    let mut text = String::new();
+    for i in 0..20 { text.push_str(&format!("module m{}; endmodule\n", i)); }
+    fs::write(&src, &text).unwrap();
+
+    let mut p = Parameter::new().top("m0").hash(HashAlgo::Crc32, Some(1)).file(src.to_string_lossy().to_string());
+    assert_eq!(release(&mut p).err().unwrap()[0].exit_code(), 5);
+
+    // output over input file, which exist
+    let mut p = Parameter::new().top("m0").file(src.to_string_lossy().to_string());
+    p.out_dir = Some(dir.to_string_lossy().to_string());
+    let r = release(&mut p).unwrap();
+    assert_eq!(write_output(&p, &r.files).unwrap_err()[0].exit_code(), 6);
+
+    let manifest = dir.join("m.json");
+    fs::write(&manifest, "{\"modules\": [{\"name\": \"m1\", \"checksum\": \"xyz\"}]}").unwrap();
+    let p = parse_args(args(&["verify", "--manifest", &manifest.to_string_lossy(), "--no-cache", &src.to_string_lossy()])).unwrap();
+    let err = verify(p).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(err[0].exit_code(), 7);
+}
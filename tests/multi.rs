@@ -0,0 +1,37 @@
+use std::fs;
+
+use shim_release::{multi, parse_multi, SHARED_FILE};
+
+fn args(dir: &str, rest: &str) -> Vec<String> {
+    let mut res: Vec<String> = vec!["-o".to_string(), format!("{}/out", dir), "--no-cache".to_string()];
+    res.extend(rest.split_whitespace().map(|x| x.replace("{}", dir)));
+    res
+}
+
+#[test]
+fn common_manifest_and_stub_per_package() {
+    let dir = std::env::temp_dir().join(format!("shim-release-multi-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let d = dir.to_string_lossy().to_string();
+
+    fs::write(dir.join("a.sv"), "module leaf; endmodule\nmodule top; leaf l(); bb x(); endmodule\n").unwrap();
+    fs::write(dir.join("b.sv"), "module leaf; endmodule\nmodule top; leaf l(); bb y(); endmodule\n").unwrap();
+
+    let rest = "--manifest {}/m.json --stub {}/s.sv --group -p a -r 1 -t top {}/a.sv --group -p b -r 3 -t top {}/b.sv";
+    multi(parse_multi(args(&d, rest)).unwrap()).unwrap();
+
+    for f in ["a/m.json", "a/s.sv", "b/m.json", "b/s.sv", SHARED_FILE] {
+        assert!(dir.join("out").join(f).exists(), "{} not written", f);
+    }
+    assert!(fs::read_to_string(dir.join("out/b/m.json")).unwrap().contains("\"package\": \"b\""));
+
+    // existing file of one package stop the whole run before anything written
+    fs::remove_dir_all(dir.join("out/b")).unwrap();
+    fs::remove_file(dir.join("out").join(SHARED_FILE)).unwrap();
+    assert!(multi(parse_multi(args(&d, rest)).unwrap()).is_err());
+    assert!(!dir.join("out/b").exists());
+    assert!(!dir.join("out").join(SHARED_FILE).exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,309 @@
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+use shim_release::{release, Error, HashAlgo, Parameter, Release};
+
+// write sources under a fresh directory of the test, add them to parameter & release
+fn try_run(test: &str, files: &[(&str, &str)], p: Parameter) -> Result<Release, Vec<Error>> {
+    let dir = std::env::temp_dir().join(format!("shim-release-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut p = p.incdir(dir.to_string_lossy().to_string());
+
+    for (name, text) in files.iter() {
+        let path: PathBuf = dir.join(name);
+        fs::write(&path, text).unwrap();
+        if !name.ends_with(".svh") { p = p.file(path.to_string_lossy().to_string()); }
+    }
+
+    let res = release(&mut p);
+    fs::remove_dir_all(&dir).unwrap();
+    res
+}
+
+fn run(test: &str, files: &[(&str, &str)], p: Parameter) -> Release {
+    try_run(test, files, p).unwrap_or_else(|e| panic!("{}", e[0]))
+}
+
+// rewritten text of the only or given file
+fn text(r: &Release, name: &str) -> String {
+    r.files.iter().find(|(k, _)| k.ends_with(name)).map(|(_, v)| v.text.clone()).unwrap()
+}
+
+#[test]
+fn license_header_end_at_blank_line() {
+    let src = "// LICENSE\n\n// internal note: do not ship\nmodule m; endmodule\n";
+    let r = run("header-blank", &[("a.sv", src)], Parameter::new().top("m").strip_comments(true));
+
+    assert_eq!(text(&r, "a.sv"), "// LICENSE\n\nmodule m_r0; endmodule\n");
+}
+
+#[test]
+fn license_header_end_at_code() {
+    let src = "/* LICENSE */\nmodule m; // inline\nendmodule\n";
+    let r = run("header-code", &[("a.sv", src)], Parameter::new().top("m").strip_comments(true));
+
+    assert!(text(&r, "a.sv").starts_with("/* LICENSE */\nmodule m_r0;"));
+    assert!(!text(&r, "a.sv").contains("inline"));
+}
+
+#[test]
+fn suffix_collision_of_distinct_modules() {
+    let mut src = String::new();
+    for i in 0..20 { src.push_str(&format!("module m{}; endmodule\n", i)); }
+
+    let p = Parameter::new().top("m0").hash(HashAlgo::Crc32, Some(1));
+    let err = try_run("suffix-collision", &[("a.sv", &src)], p).err().expect("collision not detected");
+
+    assert!(matches!(err[0], Error::Rewrite(_)));
+    assert!(err[0].to_string().contains("same checksum suffix"));
+}
+
+#[test]
+fn prune_drop_comment_before_declaration() {
+    let src = "module top; endmodule\n/* block */ module unusedm; endmodule\nmodule keep; endmodule\n";
+    let expect = "module top_r0; endmodule\nmodule keep_r0; endmodule\n";
+
+    let r = run("prune-comment", &[("a.sv", src)], Parameter::new().top("top").top("keep").prune());
+    assert_eq!(text(&r, "a.sv"), expect);
+
+    let p = Parameter::new().top("top").top("keep").prune().preserve_directives();
+    let r = run("prune-comment-preserve", &[("a.sv", src)], p);
+    assert_eq!(text(&r, "a.sv"), expect);
+}
+
+#[test]
+fn prune_keep_newline_after_code() {
+    let src = "module top; endmodule module dead; endmodule\nmodule keep; endmodule\n";
+    let r = run("prune-code-before", &[("a.sv", src)], Parameter::new().top("top").top("keep").prune());
+
+    assert_eq!(text(&r, "a.sv"), "module top_r0; endmodule \nmodule keep_r0; endmodule\n");
+}
+
+#[test]
+fn preserve_reject_declaration_from_include() {
+    let files = [("defs.svh", "module inc_leaf; endmodule\n"),
+                 ("top.sv", "`include \"defs.svh\"\nmodule top; inc_leaf u(); endmodule\n")];
+
+    let p = Parameter::new().top("top").preserve_directives();
+    let err = try_run("preserve-include", &files, p).err().expect("no error");
+    assert!(err[0].to_string().contains("come from included"));
+
+    // default output has the header expanded & renamed
+    let r = run("default-include", &files, Parameter::new().top("top"));
+    assert!(!text(&r, "top.sv").contains("inc_leaf;"));
+}
+
+#[test]
+fn generate_nested_instantiation() {
+    let src = "module leaf; endmodule\n\
+               module top #(parameter N = 2, parameter M = 0) ();\n\
+               \x20 for (genvar i = 0; i < N; i++) begin : g\n\
+               \x20   if (M == 0) begin : a leaf u (); end\n\
+               \x20   else case (M) 1: leaf v (); default: begin : c leaf w (); end endcase\n\
+               \x20 end\n\
+               endmodule\n";
+
+    for p in [Parameter::new().top("top"), Parameter::new().top("top").preserve_directives()] {
+        let r = run("generate", &[("a.sv", src)], p);
+        let leaf = &r.renames["leaf"];
+        let t = text(&r, "a.sv");
+
+        assert!(!t.contains("leaf "));
+        for inst in ["u", "v", "w"] { assert!(t.contains(&format!("{} {} ();", leaf, inst))); }
+    }
+}
+
+#[test]
+fn bind_target() {
+    let src = "module dut; endmodule\nmodule chk; endmodule\nmodule top; dut d (); endmodule\nbind dut chk c ();\n";
+
+    for p in [Parameter::new().top("top"), Parameter::new().top("top").preserve_directives()] {
+        let r = run("bind", &[("a.sv", src)], p);
+        let bind = format!("bind {} {} c ();", r.renames["dut"], r.renames["chk"]);
+
+        assert!(text(&r, "a.sv").contains(&bind));
+    }
+}
+
+#[test]
+fn macro_body_module_name() {
+    let src = "`define LEAF leaf\nmodule leaf; endmodule\nmodule top; `LEAF a (); endmodule\n";
+
+    // expanded in default mode, body of `define renamed in preserve mode
+    let r = run("macro-body", &[("a.sv", src)], Parameter::new().top("top"));
+    assert!(text(&r, "a.sv").contains(&format!("module top_r0; {} a (); endmodule", r.renames["leaf"])));
+
+    let r = run("macro-body-preserve", &[("a.sv", src)], Parameter::new().top("top").preserve_directives());
+    let leaf = &r.renames["leaf"];
+    let expect = format!("`define LEAF {}\nmodule {}; endmodule\nmodule top_r0; `LEAF a (); endmodule\n", leaf, leaf);
+    assert_eq!(text(&r, "a.sv"), expect);
+}
+
+#[test]
+fn macro_argument_module_name() {
+    let src = "`define INST(m) m u_``m ();\nmodule leaf; endmodule\nmodule top;\n  `INST(leaf)\nendmodule\n";
+
+    let r = run("macro-arg", &[("a.sv", src)], Parameter::new().top("top"));
+    assert!(text(&r, "a.sv").contains(&format!("{} u_leaf ();", r.renames["leaf"])));
+
+    // call site is kept as is, renaming only the declaration would not elaborate
+    let p = Parameter::new().top("top").preserve_directives();
+    let err = try_run("macro-arg-preserve", &[("a.sv", src)], p).err().expect("no error");
+    assert!(err[0].to_string().contains("come from macro argument"));
+}
+
+#[test]
+fn legacy_hash_match_old_release() {
+    let src = "`define W 4\n\
+               module leaf(input [`W-1:0] a); endmodule\n\
+               package pk; parameter P = 1; endpackage\n\
+               module mid; leaf l(); endmodule\n\
+               module top; mid m(); leaf x(); endmodule\n";
+
+    // names given by release before token canonicalization & child folding
+    let r = run("legacy-hash", &[("a.sv", src)], Parameter::new().pkg("pk1").rev(3).top("top").legacy_hash());
+    assert_eq!(r.renames["leaf"], "leaf_b9ad07b2");
+    assert_eq!(r.renames["mid"], "mid_f8e33287");
+}
+
+#[test]
+fn declaration_line_in_origin_file() {
+    let files = [("defs.svh", "// header\n\nmodule inc_leaf; endmodule\n"),
+                 ("top.sv", "`include \"defs.svh\"\n\nmodule top; inc_leaf u(); endmodule\nmodule other; endmodule\n")];
+
+    let r = run("decl-line", &files, Parameter::new().top("top"));
+    let decl = &r.analysis.decl_map;
+
+    assert!(decl["inc_leaf"].file.ends_with("defs.svh"));
+    assert_eq!(decl["inc_leaf"].line, 3);
+    assert!(decl["top"].file.ends_with("top.sv"));
+    assert_eq!((decl["top"].line, decl["other"].line), (3, 4));
+}
+
+#[test]
+fn prune_keep_bind_through_instance_path() {
+    let src = "module leaf; endmodule\nmodule tb; endmodule\nmodule dead; endmodule\n\
+               module top; leaf u0 (); endmodule\nbind top.u0 tb t ();\n";
+    let r = run("prune-bind-path", &[("a.sv", src)], Parameter::new().top("top").prune());
+    let t = text(&r, "a.sv");
+
+    assert!(t.contains(&format!("module {};", r.renames["tb"])));
+    assert!(t.contains(&format!("bind top_r0.u0 {} t ();", r.renames["tb"])));
+    assert!(!t.contains("module dead"));
+}
+
+#[test]
+fn prune_keep_config_use_target() {
+    let src = "module leaf; endmodule\nmodule leaf2; endmodule\nmodule dead; endmodule\n\
+               module top; leaf u0 (); endmodule\n\
+               config cfg; design top; instance top.u0 use work.leaf2; endconfig\n";
+    let r = run("prune-config", &[("a.sv", src)], Parameter::new().top("top").prune());
+    let t = text(&r, "a.sv");
+
+    assert!(t.contains(&format!("module {};", r.renames["leaf2"])));
+    assert!(t.contains(&format!("use work.{};", r.renames["leaf2"])));
+    assert!(!t.contains("module dead"));
+}
+
+#[test]
+fn bind_path_fold_checker_digest() {
+    let src = |body: &str| format!("module chk; {} endmodule\nmodule leaf; endmodule\n\
+                                    module mid; leaf u0 (); endmodule\nmodule top; mid m (); endmodule\n\
+                                    bind mid.u0 chk c ();\n", body);
+
+    // checker bound through path is a child of path root, as `bind mid chk c ();` would be
+    let a = run("bind-path-a", &[("a.sv", &src(""))], Parameter::new().top("top"));
+    let b = run("bind-path-b", &[("a.sv", &src("wire w;"))], Parameter::new().top("top"));
+
+    assert!(a.analysis.child_map["mid"].contains("chk"));
+    assert_eq!(a.renames["leaf"], b.renames["leaf"]);
+    assert_ne!(a.renames["mid"], b.renames["mid"]);
+}
+
+#[test]
+fn auto_top_uninstantiated_module() {
+    let src = "module leaf; endmodule\n\
+               module top; leaf u (); endmodule\n\
+               module tb; top dut (); endmodule\n\
+               module spare; endmodule\n\
+               interface bus; endinterface\n";
+    let r = run("auto-top", &[("a.sv", src)], Parameter::new().auto_top(None));
+
+    assert_eq!(r.renames["tb"], "tb_r0");
+    assert_eq!(r.renames["spare"], "spare_r0");
+    assert_ne!(r.renames["top"], "top_r0");
+    assert_ne!(r.renames["bus"], "bus_r0");
+    assert!(text(&r, "a.sv").contains(&format!("module tb_r0; {} dut (); endmodule", r.renames["top"])));
+
+    // found tops checked against --expect-tops, given top keep
+    let r = run("auto-top-expect", &[("a.sv", src)], Parameter::new().top("spare").auto_top(Some(2)));
+    assert_eq!(r.renames["spare"], "spare_r0");
+
+    let err = try_run("auto-top-mismatch", &[("a.sv", src)], Parameter::new().auto_top(Some(1))).err().unwrap();
+    assert!(matches!(err[0], Error::Rewrite(_)));
+    assert!(err[0].to_string().contains("expect 1 top(s) but found 2: spare, tb"), "{}", err[0]);
+}
+
+#[test]
+fn content_addressed_share_name_across_release() {
+    let src = "module leaf; endmodule\nmodule mid; leaf u (); endmodule\nmodule top; mid m (); endmodule\n";
+    let p = |pkg: &str, rev: usize| Parameter::new().top("top").pkg(pkg).rev(rev);
+
+    let a = run("content-a", &[("a.sv", src)], p("soc", 1).content_addressed());
+    let b = run("content-b", &[("a.sv", src)], p("gpu", 7).content_addressed());
+
+    assert_eq!(a.renames["leaf"], b.renames["leaf"]);
+    assert_eq!(a.renames["mid"], b.renames["mid"]);
+    assert_eq!(a.renames["top"], "top_r1");
+    assert_eq!(b.renames["top"], "top_r7");
+
+    // without it, pkg & rev salt every digest
+    let c = run("content-c", &[("a.sv", src)], p("soc", 1));
+    let d = run("content-d", &[("a.sv", src)], p("soc", 2));
+
+    assert_ne!(c.renames["leaf"], a.renames["leaf"]);
+    assert_ne!(c.renames["leaf"], d.renames["leaf"]);
+}
+
+#[test]
+fn rename_package_interface_and_reference() {
+    let src = "package pk; typedef logic [7:0] byte_t; endpackage\n\
+               interface bus; logic v; modport mp (input v); endinterface\n\
+               program prog; endprogram\n\
+               module dev (bus.mp b, bus p); import pk::*; pk::byte_t d; endmodule\n\
+               module top;\n\
+               import pk::byte_t;\n\
+               bus u_bus ();\n\
+               dev u_dev (.b(u_bus), .p(u_bus));\n\
+               prog u_prog ();\n\
+               virtual bus vb;\n\
+               virtual interface bus vi;\n\
+               endmodule\n";
+    let other = "module other; pk::byte_t x; bus u (); endmodule\n";
+    let r = run("rename-package", &[("a.sv", src), ("b.sv", other)], Parameter::new().top("top"));
+    let t = text(&r, "a.sv");
+    let (pk, bus, prog, dev) = (&r.renames["pk"], &r.renames["bus"], &r.renames["prog"], &r.renames["dev"]);
+
+    for (m, n) in [("pk", pk), ("bus", bus), ("prog", prog)] {
+        assert!(n.starts_with(&format!("{}_", m)) && n.len() == m.len() + 9, "{} -> {}", m, n);
+    }
+
+    assert!(t.contains(&format!("package {};", pk)));
+    assert!(t.contains(&format!("interface {};", bus)));
+    assert!(t.contains(&format!("program {};", prog)));
+    assert!(t.contains(&format!("module {} ({}.mp b, {} p); import {}::*; {}::byte_t d; endmodule", dev, bus, bus, pk, pk)));
+    assert!(t.contains(&format!("import {}::byte_t;", pk)));
+    assert!(t.contains(&format!("{} u_bus ();", bus)));
+    assert!(t.contains(&format!("{} u_prog ();", prog)));
+    assert!(t.contains(&format!("virtual {} vb;", bus)));
+    assert!(t.contains(&format!("virtual interface {} vi;", bus)));
+
+    // same name from other file
+    assert!(text(&r, "b.sv").contains(&format!("{}::byte_t x; {} u ();", pk, bus)));
+
+    // every old name gone, member names keep
+    let words: BTreeSet<&str> = t.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').collect();
+    for m in ["pk", "bus", "prog", "dev"] { assert!(!words.contains(m), "{}", m); }
+    assert!(words.contains("byte_t") && words.contains("mp"));
+}